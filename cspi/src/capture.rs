@@ -0,0 +1,302 @@
+use clap::ValueEnum;
+use fs2::FileExt;
+use std::fs::{remove_file, File, OpenOptions};
+use std::io::{self, BufReader, ErrorKind, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+
+use crate::commands::NEXMON_COLLECT_BYTES_STR;
+
+/// compression level used for xz (0 to 9)
+const XZ_LEVEL: u32 = 6;
+/// compression level used for zstd (1 to 22)
+const ZSTD_LEVEL: i32 = 3;
+/// first bytes of a zstd and an xz stream
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: [u8; 6] = [0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00];
+
+/// compression of captures
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Compression {
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Zstd => "zst",
+            Compression::Xz => "xz",
+        }
+    }
+}
+
+/// file a capture is written to, compressed while writing if requested
+enum CaptureOutput {
+    Plain(File),
+    Zstd(zstd::Encoder<'static, File>),
+    Xz(XzEncoder<File>),
+}
+
+impl CaptureOutput {
+    fn new(file: File, compression: Option<Compression>) -> io::Result<CaptureOutput> {
+        Ok(match compression {
+            None => CaptureOutput::Plain(file),
+            Some(Compression::Zstd) => CaptureOutput::Zstd(zstd::Encoder::new(file, ZSTD_LEVEL)?),
+            Some(Compression::Xz) => CaptureOutput::Xz(XzEncoder::new(file, XZ_LEVEL)),
+        })
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            CaptureOutput::Plain(file) => file,
+            CaptureOutput::Zstd(encoder) => encoder,
+            CaptureOutput::Xz(encoder) => encoder,
+        }
+    }
+
+    /// writes the end of the compressed stream
+    fn finish(self) -> io::Result<()> {
+        match self {
+            CaptureOutput::Plain(mut file) => file.flush(),
+            CaptureOutput::Zstd(encoder) => encoder.finish().map(|_| ()),
+            CaptureOutput::Xz(encoder) => encoder.finish().map(|_| ()),
+        }
+    }
+}
+
+/// writes captures in cspi's own format: every protobuf message is preceded by its length as 32 bit little endian integer
+pub struct CaptureWriter {
+    /// None once finished
+    output: Option<CaptureOutput>,
+    /// size of the (uncompressed) capture so far
+    bytes: u64,
+    /// bytes written but not yet added to the collected bytes
+    unreported_bytes: u64,
+}
+
+impl CaptureWriter {
+    /// creates the capture file, replacing any existing file
+    pub fn create(path: &Path) -> Result<CaptureWriter, String> {
+        CaptureWriter::create_compressed(path, None)
+    }
+
+    /// creates the capture file, compressing it while writing
+    pub fn create_compressed(
+        path: &Path,
+        compression: Option<Compression>,
+    ) -> Result<CaptureWriter, String> {
+        let _ = remove_file(path);
+        let output = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(path)
+            .and_then(|file| CaptureOutput::new(file, compression))
+            .map_err(|err| format!("Could not create {}. Error: {}", path.display(), err))?;
+        Ok(CaptureWriter {
+            output: Some(output),
+            bytes: 0,
+            unreported_bytes: 0,
+        })
+    }
+
+    pub fn write(&mut self, message: &[u8]) -> Result<(), String> {
+        let output = self
+            .output
+            .as_mut()
+            .ok_or("The capture has already been finished")?;
+        let writer = output.writer();
+        writer
+            .write_all(&(message.len() as u32).to_le_bytes())
+            .and_then(|_| writer.write_all(message))
+            .map_err(|err| format!("Could not write to capture. Error: {}", err))?;
+        // flushing a compressed stream for every message would ruin the compression
+        if let CaptureOutput::Plain(file) = output {
+            let _ = file.flush();
+        }
+        self.bytes += (size_of::<u32>() + message.len()) as u64;
+        self.unreported_bytes += (size_of::<u32>() + message.len()) as u64;
+
+        // don't touch the counter file for every message
+        if self.unreported_bytes >= 64 * 1024 {
+            add_collected_bytes(self.unreported_bytes);
+            self.unreported_bytes = 0;
+        }
+        Ok(())
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// completes the capture, a compressed capture is unreadable without this
+    pub fn finish(mut self) -> Result<(), String> {
+        match self.output.take() {
+            Some(output) => output
+                .finish()
+                .map_err(|err| format!("Could not finish capture. Error: {}", err)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        if let Some(output) = self.output.take() {
+            let _ = output.finish();
+        }
+        add_collected_bytes(self.unreported_bytes);
+    }
+}
+
+/// reads captures written by CaptureWriter, decompressing zstd and xz captures transparently
+pub struct CaptureReader {
+    path: PathBuf,
+    input: Box<dyn Read>,
+}
+
+impl CaptureReader {
+    /// opens a capture, its compression is detected from its first bytes rather than its extension
+    pub fn open(path: &Path) -> Result<CaptureReader, String> {
+        let open = || -> io::Result<Box<dyn Read>> {
+            let mut file = BufReader::new(File::open(path)?);
+            let mut magic = [0; XZ_MAGIC.len()];
+            let magic_len = file.read(&mut magic)?;
+            let magic = &magic[..magic_len];
+            let file = io::Cursor::new(magic.to_vec()).chain(file);
+            Ok(if magic.starts_with(&ZSTD_MAGIC) {
+                Box::new(zstd::Decoder::new(file)?)
+            } else if magic.starts_with(&XZ_MAGIC) {
+                Box::new(XzDecoder::new(file))
+            } else {
+                Box::new(file)
+            })
+        };
+        Ok(CaptureReader {
+            path: path.to_path_buf(),
+            input: open()
+                .map_err(|err| format!("Could not open {}. Error: {}", path.display(), err))?,
+        })
+    }
+
+    /// next protobuf message, None at the end of the capture
+    pub fn next_message(&mut self) -> Result<Option<Vec<u8>>, String> {
+        let mut length = [0; size_of::<u32>()];
+        match self.input.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => {
+                return Err(format!(
+                    "Could not read {}. Error: {}",
+                    self.path.display(),
+                    err
+                ))
+            }
+        }
+        let mut message = vec![0; u32::from_le_bytes(length) as usize];
+        self.input.read_exact(&mut message).map_err(|err| {
+            format!(
+                "Capture {} ends within a message. Error: {}",
+                self.path.display(),
+                err
+            )
+        })?;
+        Ok(Some(message))
+    }
+}
+
+/// adds to the total number of bytes collected, which the decoder exports as a metric.
+/// Several collecting processes may add at the same time, so the counter file is locked while it is updated.
+pub fn add_collected_bytes(bytes: u64) {
+    let add = || -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(NEXMON_COLLECT_BYTES_STR)?;
+        file.lock_exclusive()?;
+        let total = read_total(&mut file)?;
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", total + bytes)
+    };
+    let _ = add();
+}
+
+/// total number of bytes collected so far
+pub fn collected_bytes() -> u64 {
+    let read = || -> io::Result<u64> {
+        let mut file = File::open(NEXMON_COLLECT_BYTES_STR)?;
+        // not while it is being rewritten
+        file.lock_shared()?;
+        read_total(&mut file)
+    };
+    read().unwrap_or(0)
+}
+
+fn read_total(file: &mut File) -> io::Result<u64> {
+    let mut total = String::new();
+    file.read_to_string(&mut total)?;
+    Ok(total.trim().parse().unwrap_or(0))
+}
+
+/// path of a capture with the extension of its compression appended (e.g. capture.csi.zst)
+pub fn compressed_path(path: &Path, compression: Option<Compression>) -> PathBuf {
+    match compression {
+        Some(compression) => {
+            let mut compressed_path = path.as_os_str().to_owned();
+            compressed_path.push(".");
+            compressed_path.push(compression.extension());
+            PathBuf::from(compressed_path)
+        }
+        None => path.to_path_buf(),
+    }
+}
+
+/// compresses a finished capture next to it and removes the original once the compressed capture reads back
+/// completely, returns the path of the compressed capture
+pub fn compress_file(path: &Path, compression: Compression) -> Result<PathBuf, String> {
+    let compressed_path = compressed_path(path, Some(compression));
+
+    let compress = || -> io::Result<u64> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut output = CaptureOutput::new(File::create(&compressed_path)?, Some(compression))?;
+        let bytes = io::copy(&mut reader, output.writer())?;
+        output.finish()?;
+        Ok(bytes)
+    };
+    let bytes = compress().map_err(|err| {
+        let _ = remove_file(&compressed_path);
+        format!("Could not compress {}. Error: {}", path.display(), err)
+    })?;
+
+    // the original is only removed if no message got lost, e.g. because the SD card is full
+    let read_back = || -> Result<u64, String> {
+        let mut reader = CaptureReader::open(&compressed_path)?;
+        let mut read_bytes = 0;
+        while let Some(message) = reader.next_message()? {
+            read_bytes += (size_of::<u32>() + message.len()) as u64;
+        }
+        Ok(read_bytes)
+    };
+    match read_back() {
+        Ok(read_bytes) if read_bytes == bytes => {}
+        Ok(_) => {
+            let _ = remove_file(&compressed_path);
+            return Err(format!(
+                "Compressed capture {} is incomplete",
+                compressed_path.display()
+            ));
+        }
+        Err(err) => {
+            let _ = remove_file(&compressed_path);
+            return Err(err);
+        }
+    }
+    remove_file(path)
+        .map_err(|err| format!("Could not remove {}. Error: {}", path.display(), err))?;
+
+    Ok(compressed_path)
+}
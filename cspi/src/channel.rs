@@ -0,0 +1,317 @@
+use std::fmt;
+
+/// 20 MHz channels of the 2.4 GHz band (14 is only available with 20 MHz)
+const CHANNELS_2G: [u32; 14] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14];
+/// 20 MHz channels of the 5 GHz band
+const CHANNELS_5G: [u32; 25] = [
+    36, 40, 44, 48, 52, 56, 60, 64, 100, 104, 108, 112, 116, 120, 124, 128, 132, 136, 140, 144,
+    149, 153, 157, 161, 165,
+];
+/// lowest 20 MHz channel of every 40 MHz channel in the 5 GHz band
+const GROUPS_40_5G: [u32; 12] = [36, 44, 52, 60, 100, 108, 116, 124, 132, 140, 149, 157];
+/// lowest 20 MHz channel of every 80 MHz channel in the 5 GHz band
+const GROUPS_80_5G: [u32; 6] = [36, 52, 100, 116, 132, 149];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Band {
+    TwoPointFourGhz,
+    FiveGhz,
+}
+
+/// position of the control channel within a 40 MHz channel
+#[derive(Clone, Copy, PartialEq)]
+pub enum Sideband {
+    Lower,
+    Upper,
+}
+
+/// a valid channel of the 802.11 channel plan, identified by its control channel and bandwidth
+#[derive(Clone, Copy, PartialEq)]
+pub struct Channel {
+    /// 20 MHz channel frames are transmitted on (beacons, legacy frames)
+    pub control: u32,
+    pub bandwidth: u32,
+    pub band: Band,
+    /// only set for 40 MHz channels
+    pub sideband: Option<Sideband>,
+}
+
+impl Channel {
+    /// parses a channel (36), a channel with bandwidth (36/80) or a 40 MHz channel with sideband (161/40u),
+    /// using `bandwidth` if the channel does not specify one
+    pub fn parse(spec: &str, bandwidth: u32) -> Result<Channel, String> {
+        let invalid = |reason: String| format!("Invalid channel '{}': {}", spec, reason);
+
+        let (control, bandwidth_spec) = match spec.trim().split_once('/') {
+            Some((control, bandwidth_spec)) => (control, Some(bandwidth_spec.to_lowercase())),
+            None => (spec.trim(), None),
+        };
+        let control = control
+            .parse::<u32>()
+            .map_err(|_| invalid(format!("'{}' is not a channel number", control)))?;
+
+        let (bandwidth, sideband) = match bandwidth_spec.as_deref() {
+            None => (bandwidth, None),
+            Some(bandwidth_spec) => {
+                let (bandwidth_spec, sideband) = match bandwidth_spec.strip_suffix('u') {
+                    Some(bandwidth_spec) => (bandwidth_spec, Some(Sideband::Upper)),
+                    None => match bandwidth_spec.strip_suffix('l') {
+                        Some(bandwidth_spec) => (bandwidth_spec, Some(Sideband::Lower)),
+                        None => (bandwidth_spec, None),
+                    },
+                };
+                let bandwidth = bandwidth_spec
+                    .parse::<u32>()
+                    .map_err(|_| invalid(format!("'{}' is not a bandwidth", bandwidth_spec)))?;
+                (bandwidth, sideband)
+            }
+        };
+
+        Channel::new(control, bandwidth, sideband).map_err(invalid)
+    }
+
+    /// checks a control channel and bandwidth against the channel plan
+    fn new(control: u32, bandwidth: u32, sideband: Option<Sideband>) -> Result<Channel, String> {
+        let band = if CHANNELS_2G.contains(&control) {
+            Band::TwoPointFourGhz
+        } else if CHANNELS_5G.contains(&control) {
+            Band::FiveGhz
+        } else {
+            return Err(format!(
+                "{} is not a 20 MHz channel of the 2.4 GHz (1 to 14) or 5 GHz ({}) band",
+                control,
+                CHANNELS_5G
+                    .iter()
+                    .map(u32::to_string)
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ));
+        };
+
+        if sideband.is_some() && bandwidth != 40 {
+            return Err(String::from(
+                "a sideband (u or l) can only be given for 40 MHz channels",
+            ));
+        }
+
+        let sideband = match (band, bandwidth) {
+            (_, 20) => None,
+            (Band::TwoPointFourGhz, 40) => {
+                // the other half of the 40 MHz channel is 4 channels (20 MHz) away
+                let lower_possible = control + 4 <= 13;
+                let upper_possible = (5..=13).contains(&control);
+                if !lower_possible && !upper_possible {
+                    return Err(format!("{} is not part of any 40 MHz channel", control));
+                }
+                match sideband {
+                    Some(Sideband::Lower) if lower_possible => Some(Sideband::Lower),
+                    Some(Sideband::Upper) if upper_possible => Some(Sideband::Upper),
+                    None if lower_possible => Some(Sideband::Lower),
+                    None => Some(Sideband::Upper),
+                    Some(Sideband::Lower) => {
+                        return Err(format!(
+                            "{} can't be the lower half of a 40 MHz channel (use {}/40u)",
+                            control, control
+                        ))
+                    }
+                    Some(Sideband::Upper) => {
+                        return Err(format!(
+                            "{} can't be the upper half of a 40 MHz channel (use {}/40l)",
+                            control, control
+                        ))
+                    }
+                }
+            }
+            (Band::FiveGhz, 40) => {
+                let actual = if GROUPS_40_5G.contains(&control) {
+                    Sideband::Lower
+                } else if GROUPS_40_5G.contains(&(control - 4)) {
+                    Sideband::Upper
+                } else {
+                    return Err(format!("{} is not part of any 40 MHz channel", control));
+                };
+                if sideband.is_some_and(|sideband| sideband != actual) {
+                    return Err(format!(
+                        "{} is the {} half of its 40 MHz channel (use {}/40{})",
+                        control,
+                        match actual {
+                            Sideband::Lower => "lower",
+                            Sideband::Upper => "upper",
+                        },
+                        control,
+                        match actual {
+                            Sideband::Lower => "l",
+                            Sideband::Upper => "u",
+                        }
+                    ));
+                }
+                Some(actual)
+            }
+            (Band::TwoPointFourGhz, 80) => {
+                return Err(String::from(
+                    "80 MHz channels only exist in the 5 GHz band, use a bandwidth of 20 or 40",
+                ))
+            }
+            (Band::FiveGhz, 80) => {
+                if !GROUPS_80_5G
+                    .iter()
+                    .any(|first| (*first..*first + 16).contains(&control))
+                {
+                    return Err(format!("{} is not part of any 80 MHz channel", control));
+                }
+                None
+            }
+            (_, _) => {
+                return Err(format!(
+                    "{} MHz is not a supported bandwidth, use 20, 40 or 80",
+                    bandwidth
+                ))
+            }
+        };
+
+        Ok(Channel {
+            control,
+            bandwidth,
+            band,
+            sideband,
+        })
+    }
+
+    /// channel number of the center of the whole channel
+    pub fn center(&self) -> u32 {
+        match (self.bandwidth, self.sideband) {
+            (40, Some(Sideband::Lower)) => self.control + 2,
+            (40, _) => self.control - 2,
+            (80, _) => {
+                let first = GROUPS_80_5G
+                    .iter()
+                    .rev()
+                    .find(|first| **first <= self.control)
+                    .unwrap_or(&self.control);
+                first + 6
+            }
+            _ => self.control,
+        }
+    }
+
+    /// center frequency of the whole channel in MHz
+    pub fn center_frequency(&self) -> u32 {
+        match (self.band, self.center()) {
+            (Band::TwoPointFourGhz, 14) => 2484,
+            (Band::TwoPointFourGhz, center) => 2407 + 5 * center,
+            (Band::FiveGhz, center) => 5000 + 5 * center,
+        }
+    }
+
+    /// describes the channel, e.g. 161/40u (5 GHz, center channel 159 at 5795 MHz)
+    pub fn describe(&self) -> String {
+        format!(
+            "{} ({}, center channel {} at {} MHz)",
+            self,
+            match self.band {
+                Band::TwoPointFourGhz => "2.4 GHz",
+                Band::FiveGhz => "5 GHz",
+            },
+            self.center(),
+            self.center_frequency()
+        )
+    }
+}
+
+impl fmt::Display for Channel {
+    /// channel in the notation mcp expects (e.g. 36/80 or 161/40u)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}{}",
+            self.control,
+            self.bandwidth,
+            match self.sideband {
+                Some(Sideband::Lower) => "l",
+                Some(Sideband::Upper) => "u",
+                None => "",
+            }
+        )
+    }
+}
+
+/// control channel and bandwidth of a chanspec as reported by the firmware (802.11ac format)
+pub fn decode_chanspec(chanspec: u16) -> (u32, u32) {
+    let center = (chanspec & 0x00ff) as u32;
+    // number of the 20 MHz channel within the whole channel, counted from below
+    let sideband = ((chanspec >> 8) & 0x0007) as u32;
+    match chanspec & 0x3800 {
+        0x1800 => ((center + 4 * sideband).saturating_sub(2), 40),
+        0x2000 => ((center + 4 * sideband).saturating_sub(6), 80),
+        _ => (center, 20),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(spec: &str) -> Result<Channel, String> {
+        Channel::parse(spec, 20)
+    }
+
+    #[test]
+    fn parses_channel_notations() {
+        assert_eq!(parse("36").unwrap().to_string(), "36/20");
+        assert_eq!(Channel::parse("36", 80).unwrap().to_string(), "36/80");
+        assert_eq!(parse("40/40").unwrap().to_string(), "40/40u");
+        assert_eq!(parse("161/40U").unwrap().to_string(), "161/40u");
+        assert_eq!(parse("6/40").unwrap().to_string(), "6/40l");
+        assert_eq!(parse("11/40").unwrap().to_string(), "11/40u");
+        assert!(parse("36/").is_err());
+        assert!(parse("x/20").is_err());
+    }
+
+    #[test]
+    fn validates_against_channel_plan() {
+        for spec in [
+            "144/40u", "144/40", "165", "14", "149/80", "64/80", "13/40u",
+        ] {
+            assert!(parse(spec).is_ok(), "{} was rejected", spec);
+        }
+        for spec in [
+            "165/40", "14/40", "144/40l", "36/40u", "165/80", "6/80", "15", "37", "36/160",
+            "36/20u",
+        ] {
+            assert!(parse(spec).is_err(), "{} was accepted", spec);
+        }
+    }
+
+    #[test]
+    fn computes_centers() {
+        let center = |spec: &str| {
+            let channel = parse(spec).unwrap();
+            (channel.center(), channel.center_frequency())
+        };
+        assert_eq!(center("1"), (1, 2412));
+        assert_eq!(center("14"), (14, 2484));
+        assert_eq!(center("6/40"), (8, 2447));
+        assert_eq!(center("36"), (36, 5180));
+        assert_eq!(center("36/40"), (38, 5190));
+        assert_eq!(center("161/40u"), (159, 5795));
+        assert_eq!(center("144/40u"), (142, 5710));
+        assert_eq!(center("48/80"), (42, 5210));
+        assert_eq!(center("157/80"), (155, 5775));
+        assert_eq!(
+            parse("161/40u").unwrap().describe(),
+            "161/40u (5 GHz, center channel 159 at 5795 MHz)"
+        );
+    }
+
+    #[test]
+    fn decodes_chanspecs() {
+        assert_eq!(decode_chanspec(0x1006), (6, 20));
+        assert_eq!(decode_chanspec(0xd024), (36, 20));
+        assert_eq!(decode_chanspec(0xd826), (36, 40));
+        assert_eq!(decode_chanspec(0xd926), (40, 40));
+        assert_eq!(decode_chanspec(0xd99f), (161, 40));
+        assert_eq!(decode_chanspec(0xe02a), (36, 80));
+        assert_eq!(decode_chanspec(0xe32a), (48, 80));
+        assert_eq!(decode_chanspec(0xe09b), (149, 80));
+    }
+}
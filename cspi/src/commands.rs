@@ -6,8 +6,20 @@ pub mod start;
 pub mod stop;
 pub mod running;
 pub mod decode;
+pub mod watch;
+pub mod resume;
+pub mod systemd;
+pub mod sweep;
+pub mod record;
+pub mod detect;
+pub mod spectrogram;
+pub mod monitor;
+pub mod plot;
 
 pub const BINARY_PATH_STR: &str = "/home/pi/.cspi/bins/";
 pub const FIRMWARE_PATCHED_STR: &str = "/home/pi/.cspi/firmware_patched";
 pub const NEXMON_RUNNING_STR: &str = "/home/pi/.cspi/nexmon-running";
-pub const NEXMON_DECODER_PID_STR: &str = "/home/pi/.cspi/nexmon-decoder.pid";
\ No newline at end of file
+pub const NEXMON_DECODER_PID_STR: &str = "/home/pi/.cspi/nexmon-decoder.pid";
+pub const NEXMON_DECODER_HEARTBEAT_STR: &str = "/home/pi/.cspi/nexmon-decoder-heartbeat";
+pub const NEXMON_DECODER_LOG_STR: &str = "/home/pi/.cspi/nexmon-decoder.log";
+pub const NEXMON_COLLECT_BYTES_STR: &str = "/home/pi/.cspi/collect-bytes";
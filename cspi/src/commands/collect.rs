@@ -1,72 +1,111 @@
 use std::net::UdpSocket;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::fs::{remove_file, OpenOptions};
-use std::io::Write;
+use std::fs;
+use prost::Message;
 use spinner::SpinnerBuilder;
+use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR1};
+use signal_hook::flag;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::commands::running::is_running;
+use crate::capture::{add_collected_bytes, compressed_path, CaptureWriter, Compression};
+use crate::commands::running::{is_running, CollectionParameters};
+use crate::csi::NexmonData;
+use crate::sequence::SequenceTracker;
 use crate::commands::start::start;
 use crate::commands::stop::stop;
+use crate::session::{parameters_json, Session, SessionMetadata};
+use crate::trigger::{ConditionMonitor, Trigger, TriggerOptions, TRIGGER_PORT};
+use crate::processing::normalization;
 
+/// name of the capture in the session directory
+const CAPTURE_FILE: &str = "capture.csi";
+const NEXMON_CAPTURE_FILE: &str = "capture.pcap";
+
+/// collects the requested number of packets into a session directory, next to a manifest describing the capture
 pub fn collect(
-    channel: &u32,
-    bandwidth: &u32,
-    maclist: &String,
+    parameters: &CollectionParameters,
     packets: &u32,
     output: PathBuf,
     nexmon: bool,
+    compress: Option<Compression>,
+    normalize: bool,
+    metadata: &SessionMetadata,
 ) -> Result<(), String> {
     println!("Collecting {} packets of CSI...", packets);
 
-    // stop CSI collection if it is running (may be running with other parameters, so restart later in that case)
+    // stop CSI collection if it is running (may be running with other parameters, it is started with these below)
     if is_running() {
         stop()?;
     }
 
-    let mut output_str: String = output.to_str().expect("Could not convert path to string").into();
-
-    if output_str.ends_with(".pcap") && !nexmon {
-        output_str = output_str + ".csi";
-    } else if nexmon && !output_str.ends_with(".pcap") {
-        output_str = output_str + ".pcap";
+    let mut session_parameters = parameters_json(parameters);
+    session_parameters["packets"] = (*packets).into();
+    session_parameters["format"] = match nexmon {
+        true => "nexmon",
+        false => "protobuf",
     }
+    .into();
+    session_parameters["compression"] = compress.map(|compression| compression.extension()).into();
+    session_parameters["normalized"] = normalize.into();
+    let mut session = Session::create(&output, "collect", session_parameters, metadata)?;
 
     // start CSI collection
-    start(channel, bandwidth, maclist)?;
+    start(parameters)?;
 
     // dump requested number of packets to specified file
     if nexmon {
+        let output_file = session.path(NEXMON_CAPTURE_FILE);
         Command::new("tcpdump")
-            .args([
-                "-i", "wlan0", "dst", "port", "5500", "-vv", "-w", &output_str, "-c",
-            ])
+            .args(["-i", "wlan0", "dst", "port", "5500", "-vv", "-w"])
+            .arg(&output_file)
+            .arg("-c")
             .arg(format!("{}", packets))
             .status()
             .map_err(|err| format!("Error running tcpdump: {}", err))?;
+        add_collected_bytes(fs::metadata(&output_file).map_or(0, |metadata| metadata.len()));
+        session.add_file(NEXMON_CAPTURE_FILE, *packets as u64, None, None, None)?;
     } else {
-        listen(&output_str, *packets);
+        let file_name = compressed_path(Path::new(CAPTURE_FILE), compress)
+            .display()
+            .to_string();
+        let (frames, sequence_tracker) =
+            listen(&session.path(&file_name), *packets, compress, normalize)?;
+        session.add_file(&file_name, frames, Some(&sequence_tracker), None, None)?;
     }
 
     // stop CSI collection
     stop()?;
 
     println!(
-        "All done! Your collected CSI is available at {}",
-        output_str
+        "All done! Your collected CSI is available in {}",
+        session.directory().display()
     );
+    session.finish()?;
 
     Ok(())
 }
 
-fn listen(output_file: &str, packet_num: u32) {
-    let nexmon_socket = UdpSocket::bind("127.0.0.1:4400").unwrap();
+/// writes the requested number of packets to a (possibly compressed) capture, returns the number written and their loss statistics
+fn listen(
+    output_file: &Path,
+    packet_num: u32,
+    compress: Option<Compression>,
+    normalize: bool,
+) -> Result<(u64, SequenceTracker), String> {
+    let nexmon_socket = UdpSocket::bind("127.0.0.1:4400")
+        .map_err(|err| format!("Could not bind to port 4400. Error: {}", err))?;
     nexmon_socket.set_read_timeout(None).unwrap();
 
-    let _ = remove_file(output_file);
-    let mut file = OpenOptions::new().create_new(true).append(true).open(output_file).unwrap();
+    let mut capture_writer = CaptureWriter::create_compressed(output_file, compress)?;
 
     let spinner = SpinnerBuilder::new("Collecting packets...".into()).start();
+    let mut sequence_tracker = SequenceTracker::default();
+    let mut frames = 0;
 
     for i in 1..=packet_num {
         let mut packet = [0; 8192];
@@ -75,15 +114,272 @@ fn listen(output_file: &str, packet_num: u32) {
             Err(_) => continue,
             Ok(len) => {packet_len = len.0},
         }
+        let packet = prepare(&packet[..packet_len], normalize);
 
-        file.write_all(&usize::to_le_bytes(packet_len)).unwrap_or_default();
-        file.write_all(&packet[..packet_len]).unwrap_or_default();
-        let _ = file.flush();
+        capture_writer.write(&packet)?;
+        frames += 1;
+
+        if let Ok(nexmon_data) = NexmonData::decode(packet.as_slice()) {
+            sequence_tracker.track(
+                nexmon_data.source_mac,
+                nexmon_data.seq_num,
+                nexmon_data.core,
+                nexmon_data.spatial_stream,
+            );
+        }
 
         if i % 10 == 0 {
             spinner.update(format!("Collected packets: {}", i));
         }
     }
 
+    capture_writer.finish()?;
+
     println!();
+    sequence_tracker.print_summary();
+
+    Ok((frames, sequence_tracker))
+}
+
+/// processes a received message as requested before it is written, messages that cannot be decoded are kept as they are
+fn prepare(packet: &[u8], normalize: bool) -> Vec<u8> {
+    if normalize {
+        if let Ok(mut nexmon_data) = NexmonData::decode(packet) {
+            normalization::normalize(&mut nexmon_data);
+            return nexmon_data.encode_to_vec();
+        }
+    }
+    packet.to_vec()
+}
+
+/// capture of the CSI around a trigger
+struct TriggeredCapture {
+    file_name: String,
+    capture_writer: CaptureWriter,
+    frames: u64,
+    sequence_tracker: SequenceTracker,
+    reason: String,
+    /// end of the post-trigger window
+    until: Instant,
+}
+
+impl TriggeredCapture {
+    fn write(&mut self, packet: &[u8]) -> Result<(), String> {
+        self.capture_writer.write(packet)?;
+        self.frames += 1;
+        if let Ok(nexmon_data) = NexmonData::decode(packet) {
+            self.sequence_tracker.track(
+                nexmon_data.source_mac,
+                nexmon_data.seq_num,
+                nexmon_data.core,
+                nexmon_data.spatial_stream,
+            );
+        }
+        Ok(())
+    }
+
+    fn close(self, session: &mut Session) -> Result<(), String> {
+        self.capture_writer.finish()?;
+        session.add_file(
+            &self.file_name,
+            self.frames,
+            Some(&self.sequence_tracker),
+            None,
+            None,
+        )?;
+        session.add_event(&format!("{}: {}", self.file_name, self.reason))?;
+        println!("Wrote {} ({} frames)", self.file_name, self.frames);
+        Ok(())
+    }
+}
+
+/// Keeps the CSI of the last `pre_trigger` in memory until interrupted.
+/// Whenever a trigger fires, the buffered CSI and the CSI of the following `post_trigger` are written to a file in the session directory.
+pub fn collect_triggered(
+    parameters: &CollectionParameters,
+    options: &TriggerOptions,
+    output: PathBuf,
+    compress: Option<Compression>,
+    normalize: bool,
+    metadata: &SessionMetadata,
+) -> Result<(), String> {
+    println!(
+        "Collecting CSI until interrupted (Ctrl+C), keeping {} around every trigger...",
+        humantime::format_duration(options.pre_trigger + options.post_trigger)
+    );
+
+    let stop_requested = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        flag::register(signal, Arc::clone(&stop_requested))
+            .map_err(|err| format!("Could not register signal handler. Error: {}", err))?;
+    }
+    let signal_trigger = Arc::new(AtomicBool::new(false));
+    if options
+        .triggers
+        .iter()
+        .any(|trigger| matches!(trigger, Trigger::Signal))
+    {
+        flag::register(SIGUSR1, Arc::clone(&signal_trigger))
+            .map_err(|err| format!("Could not register signal handler. Error: {}", err))?;
+        println!("Send SIGUSR1 to process {} to trigger", std::process::id());
+    }
+    if is_running() {
+        stop()?;
+    }
+
+    let mut session_parameters = parameters_json(parameters);
+    session_parameters["triggers"] = options
+        .triggers
+        .iter()
+        .map(|trigger| trigger.to_string())
+        .collect::<Vec<String>>()
+        .into();
+    session_parameters["trigger_sources"] = options.sources.to_string().into();
+    session_parameters["pre_trigger_seconds"] = options.pre_trigger.as_secs_f64().into();
+    session_parameters["post_trigger_seconds"] = options.post_trigger.as_secs_f64().into();
+    session_parameters["compression"] = compress.map(|compression| compression.extension()).into();
+    session_parameters["normalized"] = normalize.into();
+    let mut session = Session::create(&output, "collect", session_parameters, metadata)?;
+
+    start(parameters)?;
+
+    // bound only once collection has started, so nothing launched by start inherits the socket
+    let trigger_socket = match options
+        .triggers
+        .iter()
+        .any(|trigger| matches!(trigger, Trigger::Socket))
+    {
+        true => {
+            let socket = UdpSocket::bind(("127.0.0.1", TRIGGER_PORT)).map_err(|err| {
+                format!("Could not bind to port {}. Error: {}", TRIGGER_PORT, err)
+            })?;
+            socket
+                .set_nonblocking(true)
+                .map_err(|err| format!("Could not configure trigger socket. Error: {}", err))?;
+            println!("Run `cspi trigger` to trigger");
+            Some(socket)
+        }
+        false => None,
+    };
+
+    let nexmon_socket = UdpSocket::bind("127.0.0.1:4400")
+        .map_err(|err| format!("Could not bind to port 4400. Error: {}", err))?;
+    nexmon_socket
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .map_err(|err| format!("Could not set read timeout. Error: {}", err))?;
+
+    let mut condition_monitor = ConditionMonitor::new(options);
+    let mut buffer: VecDeque<(Instant, Vec<u8>)> = VecDeque::new();
+    let mut capture: Option<TriggeredCapture> = None;
+    let mut trigger_number = 0;
+
+    while !stop_requested.load(Ordering::Relaxed) {
+        let mut reason = None;
+        if signal_trigger.swap(false, Ordering::Relaxed) {
+            reason = Some(String::from("SIGUSR1 received"));
+        }
+        if let Some(trigger_socket) = &trigger_socket {
+            let mut command = [0; 1024];
+            if let Ok((command_len, _)) = trigger_socket.recv_from(&mut command) {
+                let command = String::from_utf8_lossy(&command[..command_len]);
+                reason = Some(match command.trim() {
+                    "" => String::from("Triggered via socket"),
+                    command => format!("Triggered via socket: {}", command),
+                });
+            }
+        }
+
+        let mut packet = [0; 8192];
+        let packet = match nexmon_socket.recv_from(&mut packet) {
+            Ok((packet_len, _)) => Some(prepare(&packet[..packet_len], normalize)),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                ) =>
+            {
+                None
+            }
+            Err(err) => return Err(format!("Could not receive from port 4400. Error: {}", err)),
+        };
+        if let Some(nexmon_data) = packet
+            .as_ref()
+            .and_then(|packet| NexmonData::decode(packet.as_slice()).ok())
+        {
+            if let Some(condition) = condition_monitor.check(&nexmon_data) {
+                reason = reason.or(Some(condition));
+            }
+        }
+
+        let now = Instant::now();
+        if let Some(reason) = reason {
+            match &capture {
+                Some(capture) => println!(
+                    "Ignoring trigger while writing {}: {}",
+                    capture.file_name, reason
+                ),
+                None => {
+                    trigger_number += 1;
+                    let file_name = compressed_path(
+                        Path::new(&format!("trigger-{:06}.csi", trigger_number)),
+                        compress,
+                    )
+                    .display()
+                    .to_string();
+                    println!("{}, writing {}...", reason, file_name);
+                    let mut triggered_capture = TriggeredCapture {
+                        capture_writer: CaptureWriter::create_compressed(
+                            &session.path(&file_name),
+                            compress,
+                        )?,
+                        file_name,
+                        frames: 0,
+                        sequence_tracker: SequenceTracker::default(),
+                        reason,
+                        until: now + options.post_trigger,
+                    };
+                    for (_, packet) in buffer.drain(..) {
+                        triggered_capture.write(&packet)?;
+                    }
+                    capture = Some(triggered_capture);
+                }
+            }
+        }
+
+        if let Some(packet) = packet {
+            match capture.as_mut() {
+                Some(capture) => capture.write(&packet)?,
+                None => buffer.push_back((now, packet)),
+            }
+        }
+        while buffer
+            .front()
+            .is_some_and(|(received, _)| now.duration_since(*received) > options.pre_trigger)
+        {
+            buffer.pop_front();
+        }
+
+        if capture.as_ref().is_some_and(|capture| now >= capture.until) {
+            if let Some(capture) = capture.take() {
+                capture.close(&mut session)?;
+            }
+        }
+    }
+
+    println!("Finishing collection...");
+    // the post-trigger window of the last capture is cut short
+    if let Some(capture) = capture.take() {
+        capture.close(&mut session)?;
+    }
+
+    stop()?;
+
+    println!(
+        "All done! {} triggers were captured in {}",
+        trigger_number,
+        session.directory().display()
+    );
+    session.finish()?;
+
+    Ok(())
 }
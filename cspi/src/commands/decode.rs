@@ -1,102 +1,861 @@
-use crate::commands::NEXMON_DECODER_PID_STR;
-use crate::csi::{Csi, NexmonData};
-use daemonize::{Daemonize, Outcome::Child};
+use crate::channel::decode_chanspec;
+use crate::commands::{
+    running::RunningState,
+    systemd::{decoder_unit_active, restart_decoder_unit},
+    NEXMON_DECODER_HEARTBEAT_STR, NEXMON_DECODER_LOG_STR, NEXMON_DECODER_PID_STR,
+    NEXMON_RUNNING_STR,
+};
+use crate::csi::{Csi, FrameType, MimoData, NexmonData, Subscription};
+use crate::frame::{frame_type, frame_type_name};
+use crate::logging::{self, LogTarget};
+use crate::mac::MacFilter;
+use crate::metrics::{self, Metrics};
+use crate::mimo::MimoGrouper;
+use crate::processing::filter::{FilterStage, Pipeline};
+use crate::processing::normalization::normalize;
+use crate::processing::phase::add_sanitized_phase;
+use crate::processing::subcarriers::add_derived;
+use crate::sequence::{SequenceEvent, SequenceTracker};
+use crate::subscribers::Subscribers;
+use fs2::FileExt;
+use log::{debug, error, info, warn, LevelFilter};
 use prost::Message;
-use std::fs;
-use std::net::UdpSocket;
-use std::path::Path;
-use std::process::Command;
-
-/// launches decoder if it's not already running
-pub fn launch_decoder() -> Result<(), String> {
-    let pgrep_output = String::from_utf8(
-        Command::new("pgrep")
-            .args(["cspi"])
-            .output()
-            .map_err(|err| format!("Could not obtain information from pgrep. Error: {}", err))?
-            .stdout,
-    )
-    .map_err(|_| "Could not parse string returned by pgrep cspi")?;
-
-    let daemon_pid = match fs::read_to_string(NEXMON_DECODER_PID_STR) {
-        Ok(path) => path,
-        Err(_) => {
-            if !Path::new(NEXMON_DECODER_PID_STR).is_file() {
-                String::from("NOT FOUND")
-            } else {
-                String::from("")
-            }
+use sd_notify::NotifyState;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// how long to wait for the decoder to shut down after asking it to stop
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+/// how long a launched decoder is watched for stopping right away
+const LAUNCH_CHECK: Duration = Duration::from_millis(500);
+/// length of the nexmon header preceding the CSI
+const NEXMON_HEADER_LEN: usize = 18;
+/// first two bytes of every frame in nexmon format
+const NEXMON_MAGIC: [u8; 2] = [0x11, 0x11];
+
+/// settings the decoder is launched with
+pub struct DecoderOptions {
+    pub log_level: LevelFilter,
+    /// log file, defaults to standard error in the foreground and NEXMON_DECODER_LOG_STR in the background
+    pub log_file: Option<PathBuf>,
+    pub syslog: bool,
+    /// how often frame counts are logged
+    pub summary_interval: Duration,
+    /// address to serve Prometheus metrics on, disabled if None
+    pub metrics_address: Option<SocketAddr>,
+    /// fill in NexmonData.gap on port 4400
+    pub gaps: bool,
+    /// fill in amplitude, phase and the subcarrier layout on port 4400
+    pub derived: bool,
+    /// fill in the sanitized phase on port 4400
+    pub sanitize_phase: bool,
+    /// scale the CSI to the RSSI on port 4400
+    pub normalize: bool,
+    /// filter pipeline whose output is sent to subscribers asking for filtered CSI, disabled if empty
+    pub filter: Vec<FilterStage>,
+}
+
+impl Default for DecoderOptions {
+    fn default() -> Self {
+        DecoderOptions {
+            log_level: LevelFilter::Info,
+            log_file: None,
+            syslog: false,
+            summary_interval: Duration::from_secs(60),
+            metrics_address: None,
+            gaps: false,
+            derived: false,
+            sanitize_phase: false,
+            normalize: false,
+            filter: vec![],
         }
+    }
+}
+
+impl DecoderOptions {
+    fn log_target(&self, foreground: bool) -> LogTarget {
+        match (&self.log_file, self.syslog, foreground) {
+            (_, true, _) => LogTarget::Syslog,
+            (Some(log_file), _, _) => LogTarget::File(log_file.clone()),
+            (None, _, true) => LogTarget::Stderr,
+            (None, _, false) => LogTarget::File(PathBuf::from(NEXMON_DECODER_LOG_STR)),
+        }
+    }
+}
+
+/// launches the decoder in the background if it's not already running. It is started as a new process rather than
+/// forked, so it does not inherit the sockets, threads and locks of the command launching it.
+pub fn launch_decoder(options: &DecoderOptions) -> Result<(), String> {
+    if running_decoder_pid()?.is_some() {
+        println!("Decoder already running.");
+        return Ok(());
+    }
+
+    let mut command = decoder_command()?;
+    command
+        .args(["decode", "--foreground"])
+        .arg("--log-level")
+        .arg(options.log_level.to_string())
+        .arg("--summary-interval")
+        .arg(options.summary_interval.as_secs().max(1).to_string());
+    // the decoder has no terminal, so everything it has to say goes to the log
+    match options.log_target(false) {
+        LogTarget::Syslog => command.arg("--syslog"),
+        LogTarget::File(log_file) => command.arg("--log-file").arg(log_file),
+        LogTarget::Stderr => &mut command,
     };
+    if let Some(address) = options.metrics_address {
+        command.arg("--metrics").arg(address.to_string());
+    }
+    for (flag, enabled) in [
+        ("--gaps", options.gaps),
+        ("--derived", options.derived),
+        ("--sanitize-phase", options.sanitize_phase),
+        ("--normalize", options.normalize),
+    ] {
+        if enabled {
+            command.arg(flag);
+        }
+    }
+    for stage in options.filter.iter() {
+        command.arg("--filter").arg(stage.to_string());
+    }
 
-    if !pgrep_output.contains(&daemon_pid) {
-        match Daemonize::new().pid_file(NEXMON_DECODER_PID_STR).execute() {
-            Child(_) => {
-                decode();
-                return Err(String::from("Decoding stopped!"));
-            }
-            _ => {
-                println!("Launched decoder.")
-            }
+    spawn_decoder(command)
+}
+
+/// launches a decoder with the arguments another one ran with (see `decoder_arguments`), if none is running
+pub fn relaunch_decoder(arguments: &[OsString]) -> Result<(), String> {
+    if running_decoder_pid()?.is_some() {
+        println!("Decoder already running.");
+        return Ok(());
+    }
+
+    let mut command = decoder_command()?;
+    command.args(arguments);
+    // a decoder that logged to its terminal logs to the default log file instead
+    if !arguments.iter().any(|argument| {
+        let argument = argument.to_string_lossy();
+        argument == "--syslog" || argument.starts_with("--log-file")
+    }) {
+        command.arg("--log-file").arg(NEXMON_DECODER_LOG_STR);
+    }
+
+    spawn_decoder(command)
+}
+
+/// arguments the decoder with the given PID was started with, None if it can't be launched the same way
+pub fn decoder_arguments(pid: u32) -> Option<Vec<OsString>> {
+    let command_line = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let arguments: Vec<OsString> = command_line
+        .split(|byte| *byte == 0)
+        .skip(1)
+        .filter(|argument| !argument.is_empty())
+        .map(|argument| OsString::from_vec(argument.to_vec()))
+        .collect();
+    match arguments
+        .first()
+        .is_some_and(|argument| argument == "decode")
+        && arguments.iter().any(|argument| argument == "--foreground")
+    {
+        true => Some(arguments),
+        false => None,
+    }
+}
+
+fn decoder_command() -> Result<Command, String> {
+    let executable = env::current_exe()
+        .map_err(|err| format!("Could not determine path of cspi binary. Error: {}", err))?;
+    Ok(Command::new(executable))
+}
+
+/// starts the decoder and makes sure it keeps running for a moment
+fn spawn_decoder(mut command: Command) -> Result<(), String> {
+    // in its own process group, so Ctrl-C in the terminal it was launched from does not stop it
+    let mut decoder = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .env_remove("NOTIFY_SOCKET")
+        .process_group(0)
+        .spawn()
+        .map_err(|err| format!("Could not launch decoder. Error: {}", err))?;
+
+    // e.g. a port that is already taken stops it right away
+    let launched = Instant::now();
+    while launched.elapsed() < LAUNCH_CHECK {
+        if let Ok(Some(status)) = decoder.try_wait() {
+            return Err(format!(
+                "Decoder stopped right away ({}), see its log for the reason",
+                status
+            ));
+        }
+        sleep(Duration::from_millis(50));
+    }
+    // reaps the decoder if it stops while the command that launched it is still running
+    thread::spawn(move || decoder.wait());
+
+    println!("Launched decoder.");
+
+    Ok(())
+}
+
+/// runs the decoder in the foreground (e.g. as a systemd service)
+pub fn decode_foreground(options: &DecoderOptions) -> Result<(), String> {
+    logging::init(options.log_level, options.log_target(true))?;
+
+    let mut lock_file = lock_decoder()?.ok_or("Decoder already running")?;
+    write_pid(&mut lock_file)?;
+
+    decode(options).inspect_err(|err| error!("Decoder stopped: {}", err))
+}
+
+/// stops the running decoder and waits for it to shut down
+pub fn stop_decoder() -> Result<(), String> {
+    let pid = running_decoder_pid()?.ok_or("Decoder is not running")?;
+
+    // make sure the PID still belongs to cspi before signalling it
+    let executable = fs::read_link(format!("/proc/{}/exe", pid))
+        .map_err(|err| format!("Could not inspect decoder process {}. Error: {}", pid, err))?;
+    // the link ends in " (deleted)" once the binary has been replaced by an upgrade
+    let name = executable.file_name().and_then(|name| name.to_str());
+    if name.map(|name| name.trim_end_matches(" (deleted)")) != Some("cspi") {
+        return Err(format!(
+            "Process {} holding the decoder lock is not cspi ({})",
+            pid,
+            executable.display()
+        ));
+    }
+
+    Command::new("kill")
+        .args(["-TERM", &pid.to_string()])
+        .status()
+        .map_err(|err| format!("Could not stop decoder process {}. Error: {}", pid, err))?;
+
+    let stop_requested = Instant::now();
+    while running_decoder_pid()?.is_some() {
+        if stop_requested.elapsed() > STOP_TIMEOUT {
+            return Err(format!("Decoder process {} did not stop", pid));
         }
-    } else {
-        println!("Decoder already running.")
+        sleep(Duration::from_millis(100));
     }
 
+    println!("Stopped decoder.");
+
     Ok(())
 }
 
-pub fn decode() {
-    // set up read and write streams
-    let nexmon_socket = UdpSocket::bind("255.255.255.255:5500").unwrap();
-    nexmon_socket.set_read_timeout(None).unwrap();
-    let output_socket = UdpSocket::bind("127.0.0.1:4401").unwrap();
+/// stops the decoder if it is running and launches it again, through systemd if it runs as a service
+pub fn restart_decoder(options: &DecoderOptions) -> Result<(), String> {
+    if decoder_unit_active() {
+        return restart_decoder_unit();
+    }
+    if running_decoder_pid()?.is_some() {
+        stop_decoder()?;
+    }
+    launch_decoder(options)
+}
+
+/// PID of the running decoder, if there is one
+pub fn running_decoder_pid() -> Result<Option<u32>, String> {
+    let mut lock_file = match lock_decoder()? {
+        Some(lock_file) => {
+            // nobody holds the lock, so no decoder is running
+            drop(lock_file);
+            return Ok(None);
+        }
+        None => open_lock_file()?,
+    };
+
+    let mut pid = String::new();
+    lock_file
+        .read_to_string(&mut pid)
+        .map_err(|err| format!("Could not read decoder PID file. Error: {}", err))?;
+
+    pid.trim()
+        .parse()
+        .map(Some)
+        .map_err(|_| String::from("Decoder is running, but its PID file is invalid"))
+}
+
+/// tries to take the single-instance lock, returns None if another decoder holds it
+fn lock_decoder() -> Result<Option<File>, String> {
+    let lock_file = open_lock_file()?;
+    match lock_file.try_lock_exclusive() {
+        Ok(()) => Ok(Some(lock_file)),
+        Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
+        Err(err) => Err(format!("Could not lock decoder PID file. Error: {}", err)),
+    }
+}
+
+fn open_lock_file() -> Result<File, String> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(NEXMON_DECODER_PID_STR)
+        .map_err(|err| format!("Could not open decoder PID file. Error: {}", err))
+}
+
+fn write_pid(lock_file: &mut File) -> Result<(), String> {
+    lock_file
+        .set_len(0)
+        .and_then(|_| lock_file.rewind())
+        .and_then(|_| write!(lock_file, "{}", std::process::id()))
+        .map_err(|err| format!("Could not write decoder PID file. Error: {}", err))
+}
+
+pub fn decode(options: &DecoderOptions) -> Result<(), String> {
+    info!(
+        "Decoder starting (version {}, PID {})",
+        env!("CARGO_PKG_VERSION"),
+        std::process::id()
+    );
+
+    // stop gracefully on SIGTERM and SIGINT, remembering which one we got
+    let received_signal = Arc::new(AtomicUsize::new(0));
+    for signal in [SIGTERM, SIGINT] {
+        flag::register_usize(signal, Arc::clone(&received_signal), signal as usize)
+            .map_err(|err| format!("Could not register signal handler. Error: {}", err))?;
+    }
+
+    // set up read and write streams (time out regularly so the systemd watchdog can be served)
+    let nexmon_socket = UdpSocket::bind("255.255.255.255:5500").map_err(|err| {
+        error!("Could not bind to nexmon port 5500: {}", err);
+        format!("Could not bind to port 5500. Error: {}", err)
+    })?;
+    nexmon_socket
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .map_err(|err| format!("Could not set read timeout. Error: {}", err))?;
+    let output_socket = UdpSocket::bind("127.0.0.1:4401").map_err(|err| {
+        error!("Could not bind to output port 4401: {}", err);
+        format!("Could not bind to port 4401. Error: {}", err)
+    })?;
+    // subscriptions arrive on the output socket and are checked between frames
+    output_socket
+        .set_nonblocking(true)
+        .map_err(|err| format!("Could not set up output socket. Error: {}", err))?;
+    info!("Listening on port 5500, sending protobuf messages to port 4400 and subscribers");
+
+    let metrics = Arc::new(Mutex::new(Metrics::default()));
+    if let Some(address) = options.metrics_address {
+        metrics::serve(address, Arc::clone(&metrics)).inspect_err(|err| error!("{}", err))?;
+    }
+    let mut subscribers = Subscribers::new();
+    // port 4400 is served as if it had subscribed with the decoder options, always with the received NexmonData as
+    // captures expect, filtered CSI is only available to subscribers
+    let default_destination = SocketAddr::from(([127, 0, 0, 1], 4400));
+    let default_subscription = Subscription {
+        gaps: options.gaps,
+        frame_types: vec![],
+        mimo: false,
+        derived: options.derived,
+        sanitize_phase: options.sanitize_phase,
+        normalize: options.normalize,
+        filtered: false,
+    };
+    let mut running_collection = RunningCollection::new();
+    let mut mimo_grouper = MimoGrouper::new();
+    let mut pipeline = Pipeline::new(&options.filter, options.normalize);
+    if !pipeline.is_empty() {
+        info!(
+            "Filtering with {}",
+            options
+                .filter
+                .iter()
+                .map(|stage| stage.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+    let mut sequence_tracker = SequenceTracker::default();
+
+    // tell systemd we are ready (does nothing if not running as a systemd service)
+    let _ = sd_notify::notify(false, &[NotifyState::Ready]);
+    let mut watchdog_usec = 0;
+    let watchdog_interval = sd_notify::watchdog_enabled(false, &mut watchdog_usec)
+        .then(|| Duration::from_micros(watchdog_usec) / 2);
+    let mut last_watchdog = Instant::now();
+
+    let mut last_heartbeat: Option<Instant> = None;
+    let mut statistics = Statistics::new();
 
     loop {
+        let signal = received_signal.load(Ordering::Relaxed);
+        if signal != 0 {
+            info!(
+                "Shutting down: received {}",
+                if signal == SIGTERM as usize {
+                    "SIGTERM"
+                } else {
+                    "SIGINT"
+                }
+            );
+            let _ = sd_notify::notify(false, &[NotifyState::Stopping]);
+            statistics.log_summary();
+            return Ok(());
+        }
+
+        if let Some(interval) = watchdog_interval {
+            if last_watchdog.elapsed() >= interval {
+                let _ = sd_notify::notify(false, &[NotifyState::Watchdog]);
+                last_watchdog = Instant::now();
+            }
+        }
+
+        subscribers.update(&output_socket);
+        running_collection.refresh();
+        mimo_grouper.set_chains(running_collection.chains);
+        let destinations = [(&default_destination, &default_subscription)];
+
+        // send MIMO CSI that is still missing frames of some cores or spatial streams
+        let expired_groups = mimo_grouper.expire();
+        if !expired_groups.is_empty() {
+            let mut metrics = metrics.lock().unwrap_or_else(PoisonError::into_inner);
+            for (destination, subscription) in destinations.into_iter().chain(subscribers.iter()) {
+                for group in expired_groups.iter() {
+                    if subscription.mimo && wants(subscription, group.frame_type) {
+                        let encoded_vec = output_group(group, subscription).encode_to_vec();
+                        send(
+                            &output_socket,
+                            destination,
+                            &encoded_vec,
+                            &mut statistics,
+                            &mut metrics,
+                        );
+                    }
+                }
+            }
+        }
+
+        if statistics.since.elapsed() >= options.summary_interval {
+            statistics.log_summary();
+            statistics = Statistics::new();
+        }
+
         // read from 5500
         let mut message_buffer = [0; 4096];
-        let received_bytes;
-        match nexmon_socket.recv_from(&mut message_buffer) {
-            Err(_) => continue,
-            Ok(ok) => received_bytes = ok.0,
-        }
+        let received_bytes = match nexmon_socket.recv_from(&mut message_buffer) {
+            Ok(ok) => ok.0,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                continue
+            }
+            Err(err) => {
+                warn!("Could not receive from port 5500: {}", err);
+                continue;
+            }
+        };
+        statistics.received += 1;
+        let mut metrics = metrics.lock().unwrap_or_else(PoisonError::into_inner);
+        metrics.frame_received();
+        metrics.set_subscribers(subscribers.count());
 
         // decode CSI
-        let mut nexmon_data = NexmonData {
-            csi: vec![],
-            rssi: i8::from_le_bytes([message_buffer[2]]) as i32,
-            fctl: u8::from_le_bytes([message_buffer[3]]) as u32,
-            source_mac: u64::from_be_bytes([
-                0,
-                0,
-                message_buffer[4],
-                message_buffer[5],
-                message_buffer[6],
-                message_buffer[7],
-                message_buffer[8],
-                message_buffer[9],
-            ]),
-            seq_num: u16::from_le_bytes([message_buffer[10], message_buffer[11]]) as u32
+        let mut nexmon_data = match parse_frame(&message_buffer[..received_bytes]) {
+            Ok(nexmon_data) => nexmon_data,
+            Err(reason) => {
+                statistics.malformed += 1;
+                metrics.parse_error(reason);
+                // only warn once per summary interval so a broken sender can't flood the log
+                if statistics.malformed == 1 {
+                    warn!(
+                        "Dropping malformed frame of {} bytes: {}",
+                        received_bytes, reason
+                    );
+                } else {
+                    debug!(
+                        "Dropping malformed frame of {} bytes: {}",
+                        received_bytes, reason
+                    );
+                }
+                continue;
+            }
+        };
+        statistics.decoded += 1;
+        nexmon_data.timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        if !running_collection.matches(&nexmon_data) {
+            statistics.filtered += 1;
+            continue;
+        }
+        let sequence_event = sequence_tracker.track(
+            nexmon_data.source_mac,
+            nexmon_data.seq_num,
+            nexmon_data.core,
+            nexmon_data.spatial_stream,
+        );
+        metrics.frame_decoded(&nexmon_data, sequence_event);
+        if sequence_event == SequenceEvent::Duplicate {
+            debug!(
+                "Duplicate frame with sequence number {}",
+                nexmon_data.seq_num
+            );
+        }
+
+        // group the frames of all cores and spatial streams if anyone asked for MIMO CSI
+        let mut groups = vec![];
+        if destinations
+            .into_iter()
+            .chain(subscribers.iter())
+            .any(|(_, subscription)| subscription.mimo)
+        {
+            let mut grouped = nexmon_data.clone();
+            if let SequenceEvent::InOrder { lost } = sequence_event {
+                grouped.gap = lost;
+            }
+            groups = mimo_grouper.add(grouped);
+        }
+
+        // run the filter pipeline if anyone asked for filtered CSI
+        let mut filtered = vec![];
+        if !pipeline.is_empty()
+            && destinations
+                .into_iter()
+                .chain(subscribers.iter())
+                .any(|(_, subscription)| subscription.filtered && !subscription.mimo)
+        {
+            filtered = pipeline.process(&nexmon_data);
+        }
+
+        // encode as protobuf message and write to 4400 and all subscribers
+        for (destination, subscription) in destinations.into_iter().chain(subscribers.iter()) {
+            if subscription.filtered && !subscription.mimo && !pipeline.is_empty() {
+                for output in filtered.iter() {
+                    if wants(subscription, output.frame_type) {
+                        // frames made up by the pipeline did not follow a gap
+                        let sequence_event = match output.interpolated {
+                            true => SequenceEvent::InOrder { lost: 0 },
+                            false => sequence_event,
+                        };
+                        let encoded_vec =
+                            output_message(output, sequence_event, subscription).encode_to_vec();
+                        send(
+                            &output_socket,
+                            destination,
+                            &encoded_vec,
+                            &mut statistics,
+                            &mut metrics,
+                        );
+                    }
+                }
+                continue;
+            }
+            if !subscription.mimo {
+                if wants(subscription, nexmon_data.frame_type) {
+                    let encoded_vec =
+                        output_message(&nexmon_data, sequence_event, subscription).encode_to_vec();
+                    send(
+                        &output_socket,
+                        destination,
+                        &encoded_vec,
+                        &mut statistics,
+                        &mut metrics,
+                    );
+                }
+                continue;
+            }
+            for group in groups.iter() {
+                if wants(subscription, group.frame_type) {
+                    let encoded_vec = output_group(group, subscription).encode_to_vec();
+                    send(
+                        &output_socket,
+                        destination,
+                        &encoded_vec,
+                        &mut statistics,
+                        &mut metrics,
+                    );
+                }
+            }
+        }
+        drop(metrics);
+
+        // let the watchdog know CSI is still arriving (at most once per second)
+        if last_heartbeat.is_none_or(|time| time.elapsed() >= Duration::from_secs(1)) {
+            write_heartbeat();
+            last_heartbeat = Some(Instant::now());
+        }
+    }
+}
+
+/// prepares a decoded frame as requested by a subscription
+fn output_message(
+    nexmon_data: &NexmonData,
+    sequence_event: SequenceEvent,
+    subscription: &Subscription,
+) -> NexmonData {
+    let mut output = nexmon_data.clone();
+    if let (true, SequenceEvent::InOrder { lost }) = (subscription.gaps, sequence_event) {
+        output.gap = lost;
+    }
+    // amplitudes are derived from the normalized CSI
+    if subscription.normalize {
+        normalize(&mut output);
+    }
+    if subscription.derived {
+        add_derived(&mut output);
+    }
+    if subscription.sanitize_phase {
+        add_sanitized_phase(&mut output);
+    }
+    output
+}
+
+/// prepares MIMO CSI as requested by a subscription
+fn output_group(group: &MimoData, subscription: &Subscription) -> MimoData {
+    let mut output = group.clone();
+    for chain in output.chains.iter_mut() {
+        if !subscription.gaps {
+            chain.gap = 0;
+        }
+        if subscription.normalize {
+            normalize(chain);
+        }
+        if subscription.derived {
+            add_derived(chain);
+        }
+        if subscription.sanitize_phase {
+            add_sanitized_phase(chain);
+        }
+    }
+    output
+}
+
+/// whether a subscription wants to receive frames of a type
+fn wants(subscription: &Subscription, frame_type: i32) -> bool {
+    subscription.frame_types.is_empty() || subscription.frame_types.contains(&frame_type)
+}
+
+/// sends an encoded message to a destination, counting failures
+fn send(
+    output_socket: &UdpSocket,
+    destination: &SocketAddr,
+    encoded: &[u8],
+    statistics: &mut Statistics,
+    metrics: &mut Metrics,
+) {
+    if let Err(err) = output_socket.send_to(encoded, destination) {
+        statistics.send_errors += 1;
+        metrics.send_error();
+        if statistics.send_errors == 1 {
+            warn!("Could not send to {}: {}", destination, err);
+        }
+    }
+}
+
+/// decodes a frame in nexmon format, returns the reason if it is malformed
+pub fn parse_frame(frame: &[u8]) -> Result<NexmonData, &'static str> {
+    if frame.len() < NEXMON_HEADER_LEN {
+        return Err("shorter than nexmon header");
+    }
+    if frame[..2] != NEXMON_MAGIC {
+        return Err("wrong magic bytes");
+    }
+    if frame.len() == NEXMON_HEADER_LEN {
+        return Err("no CSI");
+    }
+    if !(frame.len() - NEXMON_HEADER_LEN).is_multiple_of(4) {
+        return Err("truncated CSI");
+    }
+
+    let (channel, bandwidth) = decode_chanspec(u16::from_le_bytes([frame[14], frame[15]]));
+    let mut nexmon_data = NexmonData {
+        csi: vec![],
+        rssi: i8::from_le_bytes([frame[2]]) as i32,
+        fctl: u8::from_le_bytes([frame[3]]) as u32,
+        source_mac: u64::from_be_bytes([
+            0, 0, frame[4], frame[5], frame[6], frame[7], frame[8], frame[9],
+        ]),
+        seq_num: u16::from_le_bytes([frame[10], frame[11]]) as u32,
+        core: (frame[12] & 0x07) as u32,
+        spatial_stream: ((frame[12] >> 3) & 0x07) as u32,
+        gap: 0,
+        frame_type: frame_type(frame[3]) as i32,
+        channel,
+        bandwidth,
+        // derived values are added per subscription
+        amplitude: vec![],
+        phase: vec![],
+        subcarrier: vec![],
+        subcarrier_type: vec![],
+        sanitized_phase: vec![],
+        normalized: false,
+        // set when the frame is received
+        timestamp: 0,
+        filtered_amplitude: vec![],
+        interpolated: false,
+    };
+
+    let mut csi = vec![];
+    for csi_subcarrier in frame[NEXMON_HEADER_LEN..].chunks(4) {
+        let real = i16::from_le_bytes([csi_subcarrier[0], csi_subcarrier[1]]) as i32;
+        let imaginary = i16::from_le_bytes([csi_subcarrier[2], csi_subcarrier[3]]) as i32;
+        let csi_element = Csi { real, imaginary };
+        csi.push(csi_element);
+    }
+
+    // halves of csi need to be swapped
+    let subcarrier_num = csi.len();
+    nexmon_data.csi = csi[(subcarrier_num / 2)..]
+        .iter()
+        .chain(csi[..subcarrier_num / 2].iter())
+        .cloned()
+        .collect();
+
+    Ok(nexmon_data)
+}
+
+/// parameters of the collection that is currently running, reloaded whenever it is restarted
+struct RunningCollection {
+    maclist: MacFilter,
+    frame_types: Vec<FrameType>,
+    /// frames measured per transmission (cores × spatial streams)
+    chains: usize,
+    /// modification time of the running marker the parameters were loaded from
+    loaded: Option<SystemTime>,
+    last_check: Option<Instant>,
+}
+
+impl RunningCollection {
+    fn new() -> RunningCollection {
+        RunningCollection {
+            maclist: MacFilter::default(),
+            frame_types: vec![],
+            chains: 1,
+            loaded: None,
+            last_check: None,
+        }
+    }
+
+    /// reloads the parameters if the running marker changed (checked at most once per second)
+    fn refresh(&mut self) {
+        if self
+            .last_check
+            .is_some_and(|time| time.elapsed() < Duration::from_secs(1))
+        {
+            return;
+        }
+        self.last_check = Some(Instant::now());
+
+        let modified = fs::metadata(NEXMON_RUNNING_STR)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified == self.loaded {
+            return;
+        }
+        self.loaded = modified;
+
+        // the firmware can only filter on a single frame type and a few addresses, everything else is filtered here
+        let parameters = match RunningState::load() {
+            Ok(running_state) => running_state.map(|running_state| running_state.parameters),
+            Err(err) => {
+                warn!("Could not load collection parameters: {}", err);
+                None
+            }
         };
+        match parameters {
+            Some(parameters) => {
+                self.chains = parameters.chains();
+                self.maclist = parameters.maclist;
+                self.frame_types = parameters.frame_types;
+            }
+            None => {
+                self.chains = 1;
+                self.maclist = MacFilter::default();
+                self.frame_types = vec![];
+            }
+        }
+        info!(
+            "Decoding frames from {}, {} per transmission",
+            self.maclist.describe(),
+            self.chains
+        );
+        match self.frame_types.is_empty() {
+            true => info!("Decoding frames of all types"),
+            false => info!(
+                "Only decoding frames of types {}",
+                self.frame_types
+                    .iter()
+                    .map(|frame_type| frame_type_name(*frame_type))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        }
+    }
 
-        let mut csi = vec![];
-        for csi_subcarrier in message_buffer[18..received_bytes].chunks(4) {
-            let real = i16::from_le_bytes([csi_subcarrier[0], csi_subcarrier[1]]) as i32;
-            let imaginary = i16::from_le_bytes([csi_subcarrier[2], csi_subcarrier[3]]) as i32;
-            let csi_element = Csi { real, imaginary };
-            csi.push(csi_element);
+    fn matches(&self, nexmon_data: &NexmonData) -> bool {
+        if !self.maclist.matches(nexmon_data.source_mac) {
+            return false;
         }
+        self.frame_types.is_empty()
+            || self
+                .frame_types
+                .iter()
+                .any(|frame_type| *frame_type as i32 == nexmon_data.frame_type)
+    }
+}
 
-        // halves of csi need to be swapped
-        let subcarrier_num = csi.len();
-        nexmon_data.csi = csi[(subcarrier_num/2)..].iter().chain(csi[..subcarrier_num/2].iter()).cloned().collect();
+/// frame counts for the periodic throughput summary
+struct Statistics {
+    since: Instant,
+    received: u64,
+    decoded: u64,
+    malformed: u64,
+    /// decoded, but not from a source or of a frame type collection was started for
+    filtered: u64,
+    send_errors: u64,
+}
 
-        // encode as protobuf message
-        let encoded_vec = nexmon_data.encode_to_vec();
+impl Statistics {
+    fn new() -> Statistics {
+        Statistics {
+            since: Instant::now(),
+            received: 0,
+            decoded: 0,
+            malformed: 0,
+            filtered: 0,
+            send_errors: 0,
+        }
+    }
 
-        // write to 4400
-        output_socket
-            .send_to(&encoded_vec[..], "127.0.0.1:4400")
-            .unwrap_or_default();
+    fn log_summary(&self) {
+        let seconds = self.since.elapsed().as_secs_f64();
+        info!(
+            "Throughput over {:.0}s: received={} decoded={} malformed={} filtered={} send_errors={} rate={:.1}/s",
+            seconds,
+            self.received,
+            self.decoded,
+            self.malformed,
+            self.filtered,
+            self.send_errors,
+            self.decoded as f64 / seconds.max(1.0)
+        );
+        if self.received == 0 {
+            warn!("No frames received from nexmon, check whether collection is running and the firmware is alive");
+        }
     }
 }
+
+/// saves the time the last frame was decoded at
+fn write_heartbeat() {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let _ = fs::write(NEXMON_DECODER_HEARTBEAT_STR, timestamp.to_string());
+}
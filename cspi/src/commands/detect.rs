@@ -0,0 +1,476 @@
+use prost::Message;
+use serde_json::{json, Value};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::commands::decode::running_decoder_pid;
+use crate::csi::{NexmonData, Subscription};
+use crate::mac::MacFilter;
+use crate::mqtt::MqttPublisher;
+use crate::processing::breathing::BreathingEstimator;
+use crate::processing::motion::{motion_probability, Baseline, MotionStatistic};
+
+/// subscriptions expire after 30 seconds, so they are renewed well before
+const SUBSCRIPTION_INTERVAL: Duration = Duration::from_secs(10);
+/// warn if no CSI arrived for this long
+const SILENCE_WARNING: Duration = Duration::from_secs(10);
+pub const MOTION_BASELINE_STR: &str = "/home/pi/.cspi/motion-baseline.json";
+
+/// where detection events are sent
+#[derive(Clone)]
+pub enum OutputTarget {
+    Stdout,
+    /// host:port to send every event to as a datagram
+    Udp(String),
+    /// broker (host:port) and topic to publish every event to
+    Mqtt {
+        address: String,
+        topic: String,
+    },
+}
+
+/// parses an event output: stdout, udp://<host>:<port> or mqtt://<host>[:<port>]/<topic>
+pub fn parse_output_target(output: &str) -> Result<OutputTarget, String> {
+    if output == "stdout" {
+        return Ok(OutputTarget::Stdout);
+    }
+    if let Some(address) = output.strip_prefix("udp://") {
+        if !address.contains(':') {
+            return Err(format!("UDP output '{}' needs a port", output));
+        }
+        return Ok(OutputTarget::Udp(address.to_owned()));
+    }
+    if let Some(location) = output.strip_prefix("mqtt://") {
+        let (host, topic) = location
+            .split_once('/')
+            .filter(|(host, topic)| !host.is_empty() && !topic.is_empty())
+            .ok_or(format!("MQTT output '{}' needs a broker and topic", output))?;
+        let address = match host.contains(':') {
+            true => host.to_owned(),
+            false => format!("{}:1883", host),
+        };
+        return Ok(OutputTarget::Mqtt {
+            address,
+            topic: topic.to_owned(),
+        });
+    }
+    Err(format!(
+        "Invalid output '{}' (expected stdout, udp://<host>:<port> or mqtt://<host>[:<port>]/<topic>)",
+        output
+    ))
+}
+
+enum EventOutput {
+    Stdout,
+    Udp {
+        socket: UdpSocket,
+        address: String,
+    },
+    Mqtt {
+        publisher: MqttPublisher,
+        topic: String,
+    },
+}
+
+/// sends detection events as JSON to all outputs
+pub struct EventSink {
+    outputs: Vec<EventOutput>,
+}
+
+impl EventSink {
+    pub fn open(targets: &[OutputTarget]) -> Result<EventSink, String> {
+        let targets = match targets.is_empty() {
+            true => &[OutputTarget::Stdout][..],
+            false => targets,
+        };
+        let outputs = targets
+            .iter()
+            .map(|target| match target {
+                OutputTarget::Stdout => Ok(EventOutput::Stdout),
+                OutputTarget::Udp(address) => Ok(EventOutput::Udp {
+                    socket: UdpSocket::bind("0.0.0.0:0")
+                        .map_err(|err| format!("Could not open UDP socket. Error: {}", err))?,
+                    address: address.clone(),
+                }),
+                OutputTarget::Mqtt { address, topic } => Ok(EventOutput::Mqtt {
+                    publisher: MqttPublisher::connect(address)?,
+                    topic: topic.clone(),
+                }),
+            })
+            .collect::<Result<Vec<EventOutput>, String>>()?;
+        Ok(EventSink { outputs })
+    }
+
+    /// sends an event, failing outputs are reported but don't stop detection
+    pub fn emit(&mut self, event: &Value) {
+        let encoded = event.to_string();
+        for output in self.outputs.iter_mut() {
+            let result = match output {
+                EventOutput::Stdout => {
+                    println!("{}", encoded);
+                    Ok(())
+                }
+                EventOutput::Udp { socket, address } => socket
+                    .send_to(encoded.as_bytes(), address.as_str())
+                    .map(|_| ())
+                    .map_err(|err| format!("Could not send event to {}. Error: {}", address, err)),
+                EventOutput::Mqtt { publisher, topic } => {
+                    publisher.publish(topic, encoded.as_bytes())
+                }
+            };
+            if let Err(err) = result {
+                eprintln!("{}", err);
+            }
+        }
+    }
+
+    pub fn keep_alive(&mut self) {
+        for output in self.outputs.iter_mut() {
+            if let EventOutput::Mqtt { publisher, .. } = output {
+                publisher.ping();
+            }
+        }
+    }
+}
+
+/// decoded CSI received as a subscriber of the decoder
+pub struct CsiStream {
+    socket: UdpSocket,
+    subscription: Subscription,
+    sources: MacFilter,
+    last_subscribed: Option<Instant>,
+    last_frame: Instant,
+    warned: bool,
+    /// whether to warn on standard error when no CSI arrives
+    warn_silence: bool,
+}
+
+impl CsiStream {
+    pub fn open(sources: MacFilter, subscription: Subscription) -> Result<CsiStream, String> {
+        if running_decoder_pid()?.is_none() {
+            return Err(String::from(
+                "Decoder is not running, launch it with `cspi decode` and start collection with `cspi start`",
+            ));
+        }
+        let socket = UdpSocket::bind("127.0.0.1:0")
+            .map_err(|err| format!("Could not open socket. Error: {}", err))?;
+        socket
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .map_err(|err| format!("Could not set read timeout. Error: {}", err))?;
+        Ok(CsiStream {
+            socket,
+            subscription,
+            sources,
+            last_subscribed: None,
+            last_frame: Instant::now(),
+            warned: false,
+            warn_silence: true,
+        })
+    }
+
+    /// does not warn when no CSI arrives, e.g. while the terminal shows a TUI
+    pub fn quiet(mut self) -> CsiStream {
+        self.warn_silence = false;
+        self
+    }
+
+    /// next frame from one of the sources, None if none arrived for a moment
+    pub fn next(&mut self) -> Result<Option<NexmonData>, String> {
+        if self
+            .last_subscribed
+            .is_none_or(|time| time.elapsed() >= SUBSCRIPTION_INTERVAL)
+        {
+            self.socket
+                .send_to(&self.subscription.encode_to_vec(), "127.0.0.1:4401")
+                .map_err(|err| format!("Could not subscribe to decoder. Error: {}", err))?;
+            self.last_subscribed = Some(Instant::now());
+        }
+        if self.warn_silence && !self.warned && self.last_frame.elapsed() >= SILENCE_WARNING {
+            eprintln!(
+                "No CSI received for {} seconds, is collection running (`cspi running`)?",
+                SILENCE_WARNING.as_secs()
+            );
+            self.warned = true;
+        }
+
+        let mut message = [0; 8192];
+        let message_len = match self.socket.recv_from(&mut message) {
+            Ok((message_len, _)) => message_len,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(err) => return Err(format!("Could not receive CSI. Error: {}", err)),
+        };
+        let mut nexmon_data = match NexmonData::decode(&message[..message_len]) {
+            Ok(nexmon_data) => nexmon_data,
+            Err(_) => return Ok(None),
+        };
+        if !self.sources.matches(nexmon_data.source_mac) {
+            return Ok(None);
+        }
+        // decoders before receive timestamps were added
+        if nexmon_data.timestamp == 0 {
+            nexmon_data.timestamp = now_us();
+        }
+        self.last_frame = Instant::now();
+        self.warned = false;
+        Ok(Some(nexmon_data))
+    }
+}
+
+/// settings of motion detection
+pub struct MotionOptions {
+    pub sources: MacFilter,
+    /// length of the sliding window the statistic is computed over
+    pub window: Duration,
+    /// how often the statistic is evaluated
+    pub interval: Duration,
+    /// standard deviations above the empty-room baseline from which on there is motion
+    pub threshold: f64,
+    pub baseline: PathBuf,
+    /// learn the baseline for this long instead of detecting
+    pub calibrate: Option<Duration>,
+    pub outputs: Vec<OutputTarget>,
+}
+
+/// Detects motion from the variance of the CSI amplitude over a sliding window, compared to the empty-room baseline.
+/// Emits a motion or idle event with its confidence whenever the state changes, until interrupted.
+pub fn detect_motion(options: &MotionOptions) -> Result<(), String> {
+    let stop_requested = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        flag::register(signal, Arc::clone(&stop_requested))
+            .map_err(|err| format!("Could not register signal handler. Error: {}", err))?;
+    }
+
+    // normalized CSI, so gain control does not look like motion
+    let mut stream = CsiStream::open(
+        options.sources.clone(),
+        Subscription {
+            normalize: true,
+            ..Subscription::default()
+        },
+    )?;
+    let mut statistic = MotionStatistic::new(options.window.as_micros() as u64);
+
+    if let Some(duration) = options.calibrate {
+        return calibrate_motion(
+            options,
+            duration,
+            &mut stream,
+            &mut statistic,
+            &stop_requested,
+        );
+    }
+
+    let baseline: Baseline = fs::read_to_string(&options.baseline)
+        .map_err(|err| {
+            format!(
+                "Could not read baseline {} (calibrate in the empty room with --calibrate first). Error: {}",
+                options.baseline.display(),
+                err
+            )
+        })
+        .and_then(|baseline| {
+            serde_json::from_str(&baseline)
+                .map_err(|err| format!("Invalid baseline {}. Error: {}", options.baseline.display(), err))
+        })?;
+    if (baseline.window_seconds - options.window.as_secs_f64()).abs() > 1e-3 {
+        eprintln!(
+            "Baseline was calibrated with a window of {}s, detection uses {}s, calibrate again for reliable results",
+            baseline.window_seconds,
+            options.window.as_secs_f64()
+        );
+    }
+    let mut sink = EventSink::open(&options.outputs)?;
+    eprintln!(
+        "Detecting motion on {} calibrated links, interrupt with Ctrl+C...",
+        baseline.links.len()
+    );
+
+    let mut motion: Option<bool> = None;
+    let mut last_evaluation = Instant::now();
+    while !stop_requested.load(Ordering::Relaxed) {
+        if let Some(nexmon_data) = stream.next()? {
+            statistic.add(&nexmon_data);
+        }
+        if last_evaluation.elapsed() < options.interval {
+            continue;
+        }
+        last_evaluation = Instant::now();
+        sink.keep_alive();
+
+        // the link that moved the most decides
+        let strongest = statistic
+            .compute(now_us())
+            .into_iter()
+            .filter_map(|(link, value)| {
+                baseline
+                    .score(&link, value)
+                    .map(|score| (link, value, score))
+            })
+            .max_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+        let (link, value, score) = match strongest {
+            Some(strongest) => strongest,
+            None => continue,
+        };
+
+        let probability = motion_probability(score, options.threshold);
+        let detected = probability >= 0.5;
+        if motion == Some(detected) {
+            continue;
+        }
+        motion = Some(detected);
+        sink.emit(&json!({
+            "time": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            "event": if detected { "motion" } else { "idle" },
+            "confidence": if detected { probability } else { 1.0 - probability },
+            "score": score,
+            "statistic": value,
+            "link": link,
+        }));
+    }
+
+    Ok(())
+}
+
+/// learns the motion statistic of the empty room and saves it as baseline
+fn calibrate_motion(
+    options: &MotionOptions,
+    duration: Duration,
+    stream: &mut CsiStream,
+    statistic: &mut MotionStatistic,
+    stop_requested: &AtomicBool,
+) -> Result<(), String> {
+    eprintln!(
+        "Calibrating for {}, make sure nobody is in the room...",
+        humantime::format_duration(duration)
+    );
+
+    let mut samples: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    let started = Instant::now();
+    let mut last_evaluation = Instant::now();
+    while started.elapsed() < duration {
+        if stop_requested.load(Ordering::Relaxed) {
+            return Err(String::from("Calibration interrupted, baseline not saved"));
+        }
+        if let Some(nexmon_data) = stream.next()? {
+            statistic.add(&nexmon_data);
+        }
+        // the first window is still filling up
+        if last_evaluation.elapsed() < options.interval || started.elapsed() < options.window {
+            continue;
+        }
+        last_evaluation = Instant::now();
+        for (link, value) in statistic.compute(now_us()) {
+            samples.entry(link).or_default().push(value);
+        }
+    }
+
+    let baseline = Baseline::learn(options.window.as_secs_f64(), &samples);
+    if baseline.links.is_empty() {
+        return Err(String::from(
+            "No CSI received during calibration, baseline not saved",
+        ));
+    }
+    let encoded = serde_json::to_string_pretty(&baseline)
+        .map_err(|err| format!("Could not encode baseline. Error: {}", err))?;
+    fs::write(&options.baseline, encoded + "\n").map_err(|err| {
+        format!(
+            "Could not write baseline {}. Error: {}",
+            options.baseline.display(),
+            err
+        )
+    })?;
+
+    for (link, link_baseline) in baseline.links.iter() {
+        eprintln!(
+            "{}: statistic {:.6} ± {:.6} over {} samples",
+            link, link_baseline.mean, link_baseline.deviation, link_baseline.samples
+        );
+    }
+    eprintln!("Baseline saved to {}", options.baseline.display());
+    Ok(())
+}
+
+/// settings of breathing rate estimation
+pub struct BreathingOptions {
+    pub source: MacFilter,
+    /// window the rate is estimated over
+    pub window: Duration,
+    /// how often the rate is estimated
+    pub interval: Duration,
+    /// number of subcarriers the estimate is based on
+    pub subcarriers: usize,
+    pub outputs: Vec<OutputTarget>,
+}
+
+/// Estimates the breathing rate of a person between a transmitter and the Pi over a rolling window.
+/// Emits an estimate every interval once the window is filled, until interrupted.
+pub fn detect_breathing(options: &BreathingOptions) -> Result<(), String> {
+    let stop_requested = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        flag::register(signal, Arc::clone(&stop_requested))
+            .map_err(|err| format!("Could not register signal handler. Error: {}", err))?;
+    }
+
+    let mut stream = CsiStream::open(
+        options.source.clone(),
+        Subscription {
+            normalize: true,
+            ..Subscription::default()
+        },
+    )?;
+    let mut estimator =
+        BreathingEstimator::new(options.window.as_micros() as u64, options.subcarriers);
+    let mut sink = EventSink::open(&options.outputs)?;
+    eprintln!(
+        "Estimating the breathing rate over {}, the first estimate follows once the window is filled...",
+        humantime::format_duration(options.window)
+    );
+
+    let mut last_estimate = Instant::now();
+    while !stop_requested.load(Ordering::Relaxed) {
+        if let Some(nexmon_data) = stream.next()? {
+            estimator.add(&nexmon_data);
+        }
+        if last_estimate.elapsed() < options.interval {
+            continue;
+        }
+        last_estimate = Instant::now();
+        sink.keep_alive();
+
+        if let Some(estimate) = estimator.estimate(now_us()) {
+            sink.emit(&json!({
+                "time": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+                "event": "breathing",
+                "breaths_per_minute": estimate.breaths_per_minute,
+                "confidence": estimate.confidence,
+                "link": estimate.link,
+                "subcarriers": estimate.subcarriers,
+            }));
+        }
+    }
+
+    Ok(())
+}
+
+/// microseconds since the Unix epoch, as in NexmonData.timestamp
+pub fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
@@ -0,0 +1,477 @@
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::symbols::Marker;
+use ratatui::text::Line;
+use ratatui::widgets::{Axis, Block, Chart, Dataset, GraphType, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use signal_hook::consts::SIGTERM;
+use signal_hook::flag;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::commands::decode::{parse_frame, running_decoder_pid};
+use crate::commands::detect::CsiStream;
+use crate::csi::{NexmonData, Subscription};
+use crate::frame::frame_type_name;
+use crate::mac::{format_mac, MacFilter};
+use crate::processing::phase::add_sanitized_phase;
+use crate::processing::subcarriers::{add_derived, is_occupied};
+use crate::sequence::{sequence_number, SequenceTracker};
+
+/// how often the screen is redrawn
+const REFRESH: Duration = Duration::from_millis(250);
+/// window the frame rate and mean RSSI are computed over
+const RATE_WINDOW: Duration = Duration::from_secs(2);
+/// transmitters not heard from for this long are greyed out
+const STALE: Duration = Duration::from_secs(5);
+
+/// settings of the monitor
+pub struct MonitorOptions {
+    /// read frames in nexmon format from port 5500 instead of subscribing to the decoder
+    pub raw: bool,
+    pub sources: MacFilter,
+}
+
+/// where the monitored frames come from
+enum MonitorInput {
+    Decoder(CsiStream),
+    Nexmon {
+        socket: UdpSocket,
+        sources: MacFilter,
+    },
+}
+
+impl MonitorInput {
+    fn open(options: &MonitorOptions) -> Result<MonitorInput, String> {
+        if !options.raw {
+            return Ok(MonitorInput::Decoder(
+                CsiStream::open(options.sources.clone(), Subscription::default())?.quiet(),
+            ));
+        }
+        if running_decoder_pid()?.is_some() {
+            return Err(String::from(
+                "The decoder is listening on port 5500, stop it with `cspi decode --stop` or monitor without --raw",
+            ));
+        }
+        let socket = UdpSocket::bind("255.255.255.255:5500")
+            .map_err(|err| format!("Could not bind to port 5500. Error: {}", err))?;
+        socket
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .map_err(|err| format!("Could not set read timeout. Error: {}", err))?;
+        Ok(MonitorInput::Nexmon {
+            socket,
+            sources: options.sources.clone(),
+        })
+    }
+
+    /// next frame, None if none arrived for a moment, Err(None) for a malformed frame
+    fn next(&mut self) -> Result<Option<NexmonData>, Option<String>> {
+        match self {
+            MonitorInput::Decoder(stream) => stream.next().map_err(Some),
+            MonitorInput::Nexmon { socket, sources } => {
+                let mut frame = [0; 8192];
+                let frame_len = match socket.recv_from(&mut frame) {
+                    Ok((frame_len, _)) => frame_len,
+                    Err(err)
+                        if matches!(
+                            err.kind(),
+                            ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                        ) =>
+                    {
+                        return Ok(None)
+                    }
+                    Err(err) => {
+                        return Err(Some(format!(
+                            "Could not receive from port 5500. Error: {}",
+                            err
+                        )))
+                    }
+                };
+                let nexmon_data = parse_frame(&frame[..frame_len]).map_err(|_| None)?;
+                Ok(sources
+                    .matches(nexmon_data.source_mac)
+                    .then_some(nexmon_data))
+            }
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            MonitorInput::Decoder(_) => "decoder (port 4401)",
+            MonitorInput::Nexmon { .. } => "nexmon (port 5500)",
+        }
+    }
+}
+
+/// what is known about a transmitter
+struct Transmitter {
+    /// arrival and RSSI of the frames within the rate window
+    recent: VecDeque<(Instant, i32)>,
+    /// latest frame, with amplitude and sanitized phase filled in
+    latest: NexmonData,
+    last_seen: Instant,
+}
+
+/// state of the monitor between redraws
+struct Monitor {
+    input: &'static str,
+    started: Instant,
+    transmitters: BTreeMap<u64, Transmitter>,
+    sequence_tracker: SequenceTracker,
+    malformed: u64,
+    table: TableState,
+    /// whether the plots are frozen
+    paused: bool,
+}
+
+impl Monitor {
+    fn add(&mut self, mut nexmon_data: NexmonData) {
+        self.sequence_tracker.track(
+            nexmon_data.source_mac,
+            nexmon_data.seq_num,
+            nexmon_data.core,
+            nexmon_data.spatial_stream,
+        );
+        let now = Instant::now();
+        let rssi = nexmon_data.rssi;
+        let paused = self.paused;
+        add_derived(&mut nexmon_data);
+        add_sanitized_phase(&mut nexmon_data);
+
+        let transmitter = self
+            .transmitters
+            .entry(nexmon_data.source_mac)
+            .or_insert_with(|| Transmitter {
+                recent: VecDeque::new(),
+                latest: nexmon_data.clone(),
+                last_seen: now,
+            });
+        transmitter.recent.push_back((now, rssi));
+        transmitter.last_seen = now;
+        if !paused {
+            transmitter.latest = nexmon_data;
+        }
+    }
+
+    /// drops frames that left the rate window
+    fn expire(&mut self) {
+        for transmitter in self.transmitters.values_mut() {
+            while transmitter
+                .recent
+                .front()
+                .is_some_and(|(arrival, _)| arrival.elapsed() > RATE_WINDOW)
+            {
+                transmitter.recent.pop_front();
+            }
+        }
+    }
+
+    fn select(&mut self, step: isize) {
+        let count = self.transmitters.len();
+        if count == 0 {
+            return;
+        }
+        let selected = self.table.selected().unwrap_or(0) as isize + step;
+        self.table
+            .select(Some(selected.clamp(0, count as isize - 1) as usize));
+    }
+
+    fn selected(&self) -> Option<&Transmitter> {
+        self.transmitters
+            .values()
+            .nth(self.table.selected().unwrap_or(0))
+    }
+}
+
+/// Shows a TUI with the frame rate, RSSI and loss of every transmitter, and plots of the amplitude and phase of the
+/// latest frame of the selected one, until q is pressed.
+pub fn monitor(options: &MonitorOptions) -> Result<(), String> {
+    let stop_requested = Arc::new(AtomicBool::new(false));
+    flag::register(SIGTERM, Arc::clone(&stop_requested))
+        .map_err(|err| format!("Could not register signal handler. Error: {}", err))?;
+
+    let mut input = MonitorInput::open(options)?;
+    let mut monitor = Monitor {
+        input: input.describe(),
+        started: Instant::now(),
+        transmitters: BTreeMap::new(),
+        sequence_tracker: SequenceTracker::default(),
+        malformed: 0,
+        table: TableState::default().with_selected(Some(0)),
+        paused: false,
+    };
+
+    let mut terminal = ratatui::try_init()
+        .map_err(|err| format!("Could not set up the terminal. Error: {}", err))?;
+    let result = run(&mut terminal, &mut input, &mut monitor, &stop_requested);
+    ratatui::restore();
+    result
+}
+
+fn run(
+    terminal: &mut DefaultTerminal,
+    input: &mut MonitorInput,
+    monitor: &mut Monitor,
+    stop_requested: &AtomicBool,
+) -> Result<(), String> {
+    let mut last_draw: Option<Instant> = None;
+    while !stop_requested.load(Ordering::Relaxed) {
+        match input.next() {
+            Ok(Some(nexmon_data)) => monitor.add(nexmon_data),
+            Ok(None) => {}
+            Err(None) => monitor.malformed += 1,
+            Err(Some(err)) => return Err(err),
+        }
+
+        while event::poll(Duration::ZERO)
+            .map_err(|err| format!("Could not read from the terminal. Error: {}", err))?
+        {
+            let key = match event::read() {
+                Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(())
+                }
+                KeyCode::Up | KeyCode::Char('k') => monitor.select(-1),
+                KeyCode::Down | KeyCode::Char('j') => monitor.select(1),
+                KeyCode::Char('p') => monitor.paused = !monitor.paused,
+                _ => {}
+            }
+            // react to keys right away
+            last_draw = None;
+        }
+
+        if last_draw.is_none_or(|time| time.elapsed() >= REFRESH) {
+            monitor.expire();
+            terminal
+                .draw(|frame| draw(frame, monitor))
+                .map_err(|err| format!("Could not draw to the terminal. Error: {}", err))?;
+            last_draw = Some(Instant::now());
+        }
+    }
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, monitor: &mut Monitor) {
+    let table_height = monitor.transmitters.len().clamp(1, 10) as u16 + 3;
+    let [header, table, plots, help] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(table_height),
+        Constraint::Min(8),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let received: u64 = monitor
+        .sequence_tracker
+        .sources()
+        .map(|(_, statistics)| statistics.received)
+        .sum();
+    frame.render_widget(
+        Paragraph::new(format!(
+            "cspi monitor | {} | running {} | {} frames | {} malformed{}",
+            monitor.input,
+            humantime::format_duration(Duration::from_secs(monitor.started.elapsed().as_secs())),
+            received,
+            monitor.malformed,
+            if monitor.paused { " | PAUSED" } else { "" }
+        ))
+        .style(Style::default().add_modifier(Modifier::BOLD)),
+        header,
+    );
+
+    draw_table(frame, monitor, table);
+
+    let [amplitude, phase] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(plots);
+    match monitor.selected() {
+        Some(transmitter) => {
+            let title = format!(
+                "{} core {} stream {} seq {}",
+                format_mac(transmitter.latest.source_mac),
+                transmitter.latest.core,
+                transmitter.latest.spatial_stream,
+                sequence_number(transmitter.latest.seq_num)
+            );
+            draw_plot(
+                frame,
+                amplitude,
+                &format!("Amplitude | {}", title),
+                &transmitter.latest,
+                &transmitter.latest.amplitude,
+                Color::Cyan,
+            );
+            draw_plot(
+                frame,
+                phase,
+                "Sanitized phase (rad)",
+                &transmitter.latest,
+                &transmitter.latest.sanitized_phase,
+                Color::Yellow,
+            );
+        }
+        None => frame.render_widget(
+            Paragraph::new("Waiting for CSI, is collection running (`cspi running`)?")
+                .block(Block::bordered()),
+            plots,
+        ),
+    }
+
+    frame.render_widget(
+        Paragraph::new("q quit | ↑/↓ select transmitter | p pause plots")
+            .style(Style::default().fg(Color::DarkGray)),
+        help,
+    );
+}
+
+fn draw_table(frame: &mut Frame, monitor: &mut Monitor, area: Rect) {
+    let statistics: BTreeMap<u64, _> = monitor
+        .sequence_tracker
+        .sources()
+        .map(|(source_mac, statistics)| (*source_mac, statistics.clone()))
+        .collect();
+    let rows: Vec<Row> = monitor
+        .transmitters
+        .iter()
+        .map(|(source_mac, transmitter)| {
+            let statistics = statistics.get(source_mac).cloned().unwrap_or_default();
+            let rate = transmitter.recent.len() as f64 / RATE_WINDOW.as_secs_f64();
+            let mean_rssi = match transmitter.recent.len() {
+                0 => String::from("-"),
+                frames => format!(
+                    "{:.1}",
+                    transmitter
+                        .recent
+                        .iter()
+                        .map(|(_, rssi)| *rssi as f64)
+                        .sum::<f64>()
+                        / frames as f64
+                ),
+            };
+            let latest = &transmitter.latest;
+            let row = Row::new(vec![
+                format_mac(*source_mac),
+                format!("{:.1}", rate),
+                latest.rssi.to_string(),
+                mean_rssi,
+                format!("{:.1}", statistics.loss_rate() * 100.0),
+                statistics.received.to_string(),
+                statistics.duplicates.to_string(),
+                statistics.reordered.to_string(),
+                match latest.channel {
+                    0 => String::from("-"),
+                    channel => format!("{}/{}", channel, latest.bandwidth),
+                },
+                frame_type_name(latest.frame_type()),
+                format!("{:.0}s", transmitter.last_seen.elapsed().as_secs_f64()),
+            ]);
+            match transmitter.last_seen.elapsed() >= STALE {
+                true => row.style(Style::default().fg(Color::DarkGray)),
+                false => row,
+            }
+        })
+        .collect();
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(17),
+            Constraint::Length(9),
+            Constraint::Length(5),
+            Constraint::Length(9),
+            Constraint::Length(7),
+            Constraint::Length(9),
+            Constraint::Length(5),
+            Constraint::Length(6),
+            Constraint::Length(7),
+            Constraint::Length(10),
+            Constraint::Length(9),
+        ],
+    )
+    .header(
+        Row::new(vec![
+            "Transmitter",
+            "Frames/s",
+            "RSSI",
+            "Mean RSSI",
+            "Loss %",
+            "Received",
+            "Dup.",
+            "Reord.",
+            "Channel",
+            "Type",
+            "Last seen",
+        ])
+        .style(Style::default().add_modifier(Modifier::BOLD)),
+    )
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+    .block(Block::bordered().title("Transmitters"));
+    frame.render_stateful_widget(table, area, &mut monitor.table);
+}
+
+/// plots values over the subcarrier index, null subcarriers are left out
+fn draw_plot(
+    frame: &mut Frame,
+    area: Rect,
+    title: &str,
+    nexmon_data: &NexmonData,
+    values: &[f32],
+    color: Color,
+) {
+    let count = values.len();
+    let points: Vec<(f64, f64)> = nexmon_data
+        .subcarrier
+        .iter()
+        .zip(values)
+        .filter(|(index, value)| is_occupied(**index, count) && value.is_finite())
+        .map(|(index, value)| (*index as f64, *value as f64))
+        .collect();
+    let (x_min, x_max) = bounds(points.iter().map(|(x, _)| *x));
+    let (y_min, y_max) = bounds(points.iter().map(|(_, y)| *y));
+
+    let chart = Chart::new(vec![Dataset::default()
+        .marker(Marker::Braille)
+        .graph_type(GraphType::Line)
+        .style(Style::default().fg(color))
+        .data(&points)])
+    .block(Block::bordered().title(title.to_owned()))
+    .x_axis(
+        Axis::default()
+            .title("Subcarrier")
+            .bounds([x_min, x_max])
+            .labels(axis_labels(x_min, x_max, 0)),
+    )
+    .y_axis(
+        Axis::default()
+            .bounds([y_min, y_max])
+            .labels(axis_labels(y_min, y_max, 1)),
+    );
+    frame.render_widget(chart, area);
+}
+
+/// smallest and largest value, widened if they are equal
+fn bounds(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (min, max) = values.fold((f64::MAX, f64::MIN), |(min, max), value| {
+        (min.min(value), max.max(value))
+    });
+    match min <= max {
+        true if max - min > f64::EPSILON => (min, max),
+        true => (min - 1.0, max + 1.0),
+        false => (0.0, 1.0),
+    }
+}
+
+fn axis_labels(min: f64, max: f64, precision: usize) -> Vec<Line<'static>> {
+    [min, (min + max) / 2.0, max]
+        .iter()
+        .map(|value| Line::from(format!("{:.*}", precision, value)))
+        .collect()
+}
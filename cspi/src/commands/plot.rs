@@ -0,0 +1,553 @@
+use clap::ValueEnum;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use prost::Message;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::capture::CaptureReader;
+use crate::csi::NexmonData;
+use crate::mac::{format_mac, MacFilter};
+use crate::processing::motion::link_name;
+use crate::processing::phase::add_sanitized_phase;
+use crate::processing::subcarriers::{is_occupied, subcarrier_indices};
+
+/// fonts tried in order if none is given, the first one that exists is used for all text
+const FONT_PATHS: [&str; 5] = [
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf",
+    "/usr/share/fonts/truetype/freefont/FreeSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+];
+/// time columns of a heatmap, frames within a column are averaged
+const HEATMAP_COLUMNS: usize = 400;
+/// frames drawn in amplitude and phase plots, evenly spread over the capture
+const MAX_TRACES: usize = 200;
+
+/// what to plot
+#[derive(Clone, Copy, ValueEnum)]
+pub enum PlotKind {
+    /// amplitude of every subcarrier over time
+    Heatmap,
+    /// amplitude over the subcarriers
+    Amplitude,
+    /// sanitized phase over the subcarriers
+    Phase,
+    /// RSSI of every transmitter over time
+    Rssi,
+    /// frames per second of every transmitter over time
+    Rate,
+}
+
+impl PlotKind {
+    fn name(&self) -> &'static str {
+        match self {
+            PlotKind::Heatmap => "heatmap",
+            PlotKind::Amplitude => "amplitude",
+            PlotKind::Phase => "phase",
+            PlotKind::Rssi => "rssi",
+            PlotKind::Rate => "rate",
+        }
+    }
+}
+
+/// settings of a plot
+pub struct PlotOptions {
+    pub capture: PathBuf,
+    pub kind: PlotKind,
+    /// .png or .svg file, next to the capture if None
+    pub output: Option<PathBuf>,
+    pub sources: MacFilter,
+    pub width: u32,
+    pub height: u32,
+    pub font: Option<PathBuf>,
+}
+
+/// data of a plot, gathered from the capture before drawing
+enum PlotData {
+    /// mean amplitude of every subcarrier per time column, None for columns without frames
+    Heatmap {
+        link: String,
+        subcarriers: Vec<i32>,
+        range: (f64, f64),
+        columns: Vec<Option<Vec<f32>>>,
+    },
+    /// values over the subcarriers of some frames and their mean over all frames
+    Traces {
+        title: String,
+        subcarriers: Vec<i32>,
+        traces: Vec<Vec<f32>>,
+        mean: Vec<f32>,
+    },
+    /// a line per transmitter
+    Series {
+        title: String,
+        y_label: &'static str,
+        series: BTreeMap<String, Vec<(f64, f64)>>,
+    },
+}
+
+/// Renders a plot of a capture (optionally zstd or xz compressed) to a PNG or SVG file.
+/// Null subcarriers of 64, 128 and 256 subcarrier captures are masked.
+pub fn plot(options: &PlotOptions) -> Result<(), String> {
+    let output = options
+        .output
+        .clone()
+        .unwrap_or_else(|| default_output(&options.capture, options.kind));
+    let svg = match output.extension().and_then(|extension| extension.to_str()) {
+        Some("png") => false,
+        Some("svg") => true,
+        _ => {
+            return Err(format!(
+                "Can't plot to {} (expected a .png or .svg file)",
+                output.display()
+            ))
+        }
+    };
+    register_font(options.font.as_deref())?;
+
+    let (data, timed) = match options.kind {
+        PlotKind::Heatmap => heatmap(options)?,
+        PlotKind::Amplitude | PlotKind::Phase => traces(options)?,
+        PlotKind::Rssi | PlotKind::Rate => series(options)?,
+    };
+    let x_label = match timed {
+        true => "Time (s)",
+        false => "Frame",
+    };
+
+    let size = (options.width, options.height);
+    let result = match svg {
+        true => draw(
+            SVGBackend::new(&output, size).into_drawing_area(),
+            &data,
+            x_label,
+        ),
+        false => draw(
+            BitMapBackend::new(&output, size).into_drawing_area(),
+            &data,
+            x_label,
+        ),
+    };
+    result.map_err(|err| format!("Could not draw {}. Error: {}", output.display(), err))?;
+
+    println!("Plotted {} to {}", options.kind.name(), output.display());
+    Ok(())
+}
+
+/// e.g. capture-heatmap.png next to capture.csi.zst
+fn default_output(capture: &Path, kind: PlotKind) -> PathBuf {
+    let name = capture
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = [".zst", ".xz"]
+        .iter()
+        .find_map(|extension| name.strip_suffix(extension))
+        .unwrap_or(&name);
+    let name = name.strip_suffix(".csi").unwrap_or(name);
+    capture.with_file_name(format!("{}-{}.png", name, kind.name()))
+}
+
+/// loads the font all text is drawn with, plotting needs one even for SVG to lay out the text
+fn register_font(font: Option<&Path>) -> Result<(), String> {
+    let path = match font {
+        Some(font) => font.to_path_buf(),
+        None => FONT_PATHS
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.exists())
+            .ok_or("No font found, install one with `sudo apt install fonts-dejavu-core` or pass a TrueType font with --font")?,
+    };
+    let bytes = fs::read(&path)
+        .map_err(|err| format!("Could not read font {}. Error: {}", path.display(), err))?;
+    // plotters keeps fonts for the rest of the program
+    plotters::style::register_font("sans-serif", FontStyle::Normal, Box::leak(bytes.into()))
+        .map_err(|_| format!("{} is not a TrueType font", path.display()))
+}
+
+/// calls `frame` with every frame of one of the sources and its time in seconds since the first frame of the capture,
+/// or its index in the capture if it has no receive timestamps; returns whether there were timestamps
+fn for_each_frame(
+    options: &PlotOptions,
+    mut frame: impl FnMut(f64, NexmonData),
+) -> Result<bool, String> {
+    let mut reader = CaptureReader::open(&options.capture)?;
+    let mut start: Option<u64> = None;
+    let mut index = 0;
+    while let Some(message) = reader.next_message()? {
+        // e.g. MIMO groups, which are not plotted
+        let nexmon_data = match NexmonData::decode(message.as_slice()) {
+            Ok(nexmon_data) => nexmon_data,
+            Err(_) => continue,
+        };
+        let start = *start.get_or_insert(nexmon_data.timestamp);
+        let time = match start {
+            0 => index as f64,
+            start => nexmon_data.timestamp.saturating_sub(start) as f64 / 1_000_000.0,
+        };
+        index += 1;
+        if options.sources.matches(nexmon_data.source_mac) {
+            frame(time, nexmon_data);
+        }
+    }
+    match start {
+        Some(start) => Ok(start != 0),
+        None => Err(format!(
+            "{} contains no CSI in protobuf format",
+            options.capture.display()
+        )),
+    }
+}
+
+/// link and number of subcarriers of the first frame, the only ones heatmaps and amplitude and phase plots show
+fn first_link(options: &PlotOptions) -> Result<(String, usize, usize, (f64, f64)), String> {
+    let mut first: Option<(String, usize)> = None;
+    let mut frames = 0;
+    let mut range = (f64::MAX, f64::MIN);
+    for_each_frame(options, |time, nexmon_data| {
+        // frames made up by the decoder's filters carry no CSI
+        if nexmon_data.csi.is_empty() || nexmon_data.interpolated {
+            return;
+        }
+        let (link, count) = first.get_or_insert((link_name(&nexmon_data), nexmon_data.csi.len()));
+        if *link == link_name(&nexmon_data) && *count == nexmon_data.csi.len() {
+            frames += 1;
+            range = (range.0.min(time), range.1.max(time));
+        }
+    })?;
+    let (link, count) = first.ok_or("No CSI from the given transmitters in the capture")?;
+    Ok((link, count, frames, range))
+}
+
+fn amplitude(nexmon_data: &NexmonData) -> Vec<f32> {
+    nexmon_data
+        .csi
+        .iter()
+        .map(|csi| (csi.real as f32).hypot(csi.imaginary as f32))
+        .collect()
+}
+
+fn heatmap(options: &PlotOptions) -> Result<(PlotData, bool), String> {
+    let (link, count, frames, range) = first_link(options)?;
+    let columns = frames.clamp(1, HEATMAP_COLUMNS);
+    let span = (range.1 - range.0).max(f64::EPSILON);
+
+    let mut sums = vec![(0, vec![0.0; count]); columns];
+    let timed = for_each_frame(options, |time, nexmon_data| {
+        if link_name(&nexmon_data) != link || nexmon_data.csi.len() != count {
+            return;
+        }
+        let column = (((time - range.0) / span) * (columns - 1) as f64).round() as usize;
+        let (frames, sum) = &mut sums[column.min(columns - 1)];
+        *frames += 1;
+        for (sum, amplitude) in sum.iter_mut().zip(amplitude(&nexmon_data)) {
+            *sum += amplitude;
+        }
+    })?;
+
+    Ok((
+        PlotData::Heatmap {
+            link,
+            subcarriers: subcarrier_indices(count).collect(),
+            range,
+            columns: sums
+                .into_iter()
+                .map(|(frames, sum)| {
+                    (frames > 0).then(|| sum.iter().map(|sum| sum / frames as f32).collect())
+                })
+                .collect(),
+        },
+        timed,
+    ))
+}
+
+fn traces(options: &PlotOptions) -> Result<(PlotData, bool), String> {
+    let (link, count, frames, _) = first_link(options)?;
+    let every = frames.div_ceil(MAX_TRACES).max(1);
+    let phase = matches!(options.kind, PlotKind::Phase);
+
+    let mut traces = vec![];
+    let mut sum = vec![0.0; count];
+    let mut index = 0;
+    let timed = for_each_frame(options, |_, mut nexmon_data| {
+        if link_name(&nexmon_data) != link || nexmon_data.csi.len() != count {
+            return;
+        }
+        let values = match phase {
+            true => {
+                add_sanitized_phase(&mut nexmon_data);
+                nexmon_data.sanitized_phase
+            }
+            false => amplitude(&nexmon_data),
+        };
+        for (sum, value) in sum.iter_mut().zip(values.iter()) {
+            *sum += value;
+        }
+        if index % every == 0 {
+            traces.push(values);
+        }
+        index += 1;
+    })?;
+
+    Ok((
+        PlotData::Traces {
+            title: format!(
+                "{} of {}, {} of {} frames and their mean",
+                match phase {
+                    true => "Sanitized phase (rad)",
+                    false => "Amplitude",
+                },
+                link,
+                traces.len(),
+                frames
+            ),
+            subcarriers: subcarrier_indices(count).collect(),
+            traces,
+            mean: sum.iter().map(|sum| sum / frames as f32).collect(),
+        },
+        timed,
+    ))
+}
+
+fn series(options: &PlotOptions) -> Result<(PlotData, bool), String> {
+    let rate = matches!(options.kind, PlotKind::Rate);
+    let mut series: BTreeMap<String, Vec<(f64, f64)>> = BTreeMap::new();
+    // frames per transmitter and second
+    let mut counts: BTreeMap<String, BTreeMap<u64, u64>> = BTreeMap::new();
+    let timed = for_each_frame(options, |time, nexmon_data| {
+        let source = format_mac(nexmon_data.source_mac);
+        match rate {
+            true => {
+                *counts
+                    .entry(source)
+                    .or_default()
+                    .entry(time as u64)
+                    .or_default() += 1
+            }
+            false => series
+                .entry(source)
+                .or_default()
+                .push((time, nexmon_data.rssi as f64)),
+        }
+    })?;
+    if rate && !timed {
+        return Err(String::from(
+            "The capture has no receive timestamps, it was collected before the decoder recorded them",
+        ));
+    }
+
+    let last = counts
+        .values()
+        .filter_map(|counts| counts.keys().last())
+        .max()
+        .copied()
+        .unwrap_or(0);
+    for (source, counts) in counts {
+        // seconds without frames count as well
+        let points = (0..=last)
+            .map(|second| {
+                (
+                    second as f64 + 0.5,
+                    counts.get(&second).copied().unwrap_or(0) as f64,
+                )
+            })
+            .collect();
+        series.insert(source, points);
+    }
+    if series.is_empty() {
+        return Err(String::from(
+            "No CSI from the given transmitters in the capture",
+        ));
+    }
+
+    Ok((
+        PlotData::Series {
+            title: match rate {
+                true => String::from("Frames per second"),
+                false => String::from("RSSI (dBm)"),
+            },
+            y_label: match rate {
+                true => "Frames/s",
+                false => "RSSI (dBm)",
+            },
+            series,
+        },
+        timed,
+    ))
+}
+
+fn draw<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    data: &PlotData,
+    x_label: &str,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    match data {
+        PlotData::Heatmap {
+            link,
+            subcarriers,
+            range,
+            columns,
+        } => {
+            let count = subcarriers.len();
+            let (first, last) = (subcarriers[0] as f64, subcarriers[count - 1] as f64);
+            let width = (range.1 - range.0).max(f64::EPSILON) / columns.len() as f64;
+            let mut chart = ChartBuilder::on(&root)
+                .caption(format!("Amplitude of {}", link), ("sans-serif", 20))
+                .margin(10)
+                .x_label_area_size(40)
+                .y_label_area_size(50)
+                .build_cartesian_2d(range.0..range.1 + width, first - 0.5..last + 0.5)?;
+            chart
+                .configure_mesh()
+                .disable_mesh()
+                .x_desc(x_label)
+                .y_desc("Subcarrier")
+                .draw()?;
+
+            let strongest = columns
+                .iter()
+                .flatten()
+                .flat_map(|amplitude| {
+                    amplitude
+                        .iter()
+                        .zip(subcarriers)
+                        .filter(|(_, index)| is_occupied(**index, count))
+                        .map(|(amplitude, _)| *amplitude)
+                })
+                .fold(f32::EPSILON, f32::max);
+            // null subcarriers are masked in grey across the whole capture
+            chart.draw_series(
+                subcarriers
+                    .iter()
+                    .filter(|index| !is_occupied(**index, count))
+                    .map(|index| {
+                        let index = *index as f64;
+                        Rectangle::new(
+                            [(range.0, index - 0.5), (range.1 + width, index + 0.5)],
+                            RGBColor(200, 200, 200).filled(),
+                        )
+                    }),
+            )?;
+            chart.draw_series(columns.iter().enumerate().flat_map(|(column, amplitude)| {
+                let start = range.0 + column as f64 * width;
+                amplitude
+                    .iter()
+                    .flat_map(|amplitude| amplitude.iter().zip(subcarriers))
+                    .filter(|(_, index)| is_occupied(**index, count))
+                    .map(move |(amplitude, index)| {
+                        let index = *index as f64;
+                        Rectangle::new(
+                            [(start, index - 0.5), (start + width, index + 0.5)],
+                            ViridisRGB.get_color(amplitude / strongest).filled(),
+                        )
+                    })
+            }))?;
+        }
+        PlotData::Traces {
+            title,
+            subcarriers,
+            traces,
+            mean,
+        } => {
+            let count = subcarriers.len();
+            let (first, last) = (subcarriers[0] as f64, subcarriers[count - 1] as f64);
+            let (low, high) = traces
+                .iter()
+                .flat_map(|trace| trace.iter().zip(subcarriers))
+                .filter(|(value, index)| is_occupied(**index, count) && value.is_finite())
+                .fold((f32::MAX, f32::MIN), |(low, high), (value, _)| {
+                    (low.min(*value), high.max(*value))
+                });
+            // flat traces are widened so the axis stays readable
+            let margin = match high - low > f32::EPSILON {
+                true => ((high - low) * 0.05) as f64,
+                false => 1.0,
+            };
+            let mut chart = ChartBuilder::on(&root)
+                .caption(title, ("sans-serif", 20))
+                .margin(10)
+                .x_label_area_size(40)
+                .y_label_area_size(60)
+                .build_cartesian_2d(first..last, low as f64 - margin..high as f64 + margin)?;
+            chart.configure_mesh().x_desc("Subcarrier").draw()?;
+
+            for trace in traces {
+                for segment in occupied_segments(subcarriers, trace) {
+                    chart.draw_series(LineSeries::new(segment, BLUE.mix(0.15)))?;
+                }
+            }
+            for segment in occupied_segments(subcarriers, mean) {
+                chart.draw_series(LineSeries::new(segment, RED.stroke_width(2)))?;
+            }
+        }
+        PlotData::Series {
+            title,
+            y_label,
+            series,
+        } => {
+            let points = || series.values().flatten();
+            let (x_low, x_high) = points().fold((f64::MAX, f64::MIN), |(low, high), (x, _)| {
+                (low.min(*x), high.max(*x))
+            });
+            let (y_low, y_high) = points().fold((f64::MAX, f64::MIN), |(low, high), (_, y)| {
+                (low.min(*y), high.max(*y))
+            });
+            let margin = ((y_high - y_low) * 0.05).max(1.0);
+            let mut chart = ChartBuilder::on(&root)
+                .caption(title, ("sans-serif", 20))
+                .margin(10)
+                .x_label_area_size(40)
+                .y_label_area_size(60)
+                .build_cartesian_2d(
+                    x_low..x_high.max(x_low + 1.0),
+                    y_low - margin..y_high + margin,
+                )?;
+            chart
+                .configure_mesh()
+                .x_desc(x_label)
+                .y_desc(*y_label)
+                .draw()?;
+
+            for (index, (source, points)) in series.iter().enumerate() {
+                let color = Palette99::pick(index).to_rgba();
+                chart
+                    .draw_series(LineSeries::new(points.iter().copied(), color))?
+                    .label(source)
+                    .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+            }
+            chart
+                .configure_series_labels()
+                .background_style(WHITE.mix(0.8))
+                .border_style(BLACK)
+                .draw()?;
+        }
+    }
+    root.present()?;
+    Ok(())
+}
+
+/// points of the runs of occupied subcarriers, so lines don't cross null subcarriers
+fn occupied_segments(subcarriers: &[i32], values: &[f32]) -> Vec<Vec<(f64, f64)>> {
+    let count = subcarriers.len();
+    let mut segments: Vec<Vec<(f64, f64)>> = vec![vec![]];
+    for (index, value) in subcarriers.iter().zip(values) {
+        if is_occupied(*index, count) && value.is_finite() {
+            segments
+                .last_mut()
+                .unwrap()
+                .push((*index as f64, *value as f64));
+        } else if !segments.last().unwrap().is_empty() {
+            segments.push(vec![]);
+        }
+    }
+    segments.retain(|segment| !segment.is_empty());
+    segments
+}
@@ -0,0 +1,378 @@
+use prost::Message;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fs::{self, remove_file};
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::capture::{compress_file, CaptureWriter, Compression};
+use crate::commands::decode::{
+    decoder_arguments, launch_decoder, relaunch_decoder, running_decoder_pid, DecoderOptions,
+};
+use crate::commands::running::{is_running, CollectionParameters};
+use crate::commands::start::start;
+use crate::commands::stop::stop;
+use crate::commands::systemd::decoder_unit_active;
+use crate::csi::NexmonData;
+use crate::sequence::SequenceTracker;
+use crate::session::{parameters_json, Session, SessionMetadata};
+
+/// how often the recorder checks whether the decoder is still running
+const DECODER_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// settings of a long-running recording
+pub struct RecordOptions {
+    /// segments are closed after this time
+    pub rotate_every: Option<Duration>,
+    /// segments are closed once they reach this size in bytes
+    pub rotate_size: Option<u64>,
+    /// compression of closed segments
+    pub compress: Option<Compression>,
+    /// the oldest segments are deleted once the closed segments take up more bytes than this
+    pub max_disk: Option<u64>,
+}
+
+/// segment currently being recorded to
+struct Segment {
+    name: String,
+    path: PathBuf,
+    capture_writer: CaptureWriter,
+    frames: u64,
+    opened: Instant,
+}
+
+impl Segment {
+    fn open(session: &Session, number: u32) -> Result<Segment, String> {
+        let name = format!("segment-{:06}.csi", number);
+        let path = session.path(&name);
+        Ok(Segment {
+            capture_writer: CaptureWriter::create(&path)?,
+            name,
+            path,
+            frames: 0,
+            opened: Instant::now(),
+        })
+    }
+
+    fn is_due(&self, options: &RecordOptions) -> bool {
+        options
+            .rotate_every
+            .is_some_and(|rotate_every| self.opened.elapsed() >= rotate_every)
+            || options
+                .rotate_size
+                .is_some_and(|rotate_size| self.capture_writer.bytes() >= rotate_size)
+    }
+}
+
+/// decoder the recording depends on
+struct DecoderWatch {
+    pid: Option<u32>,
+    /// arguments of the last decoder seen, so a relaunched one decodes the same way
+    arguments: Option<Vec<OsString>>,
+    /// why the last relaunch failed, so a decoder that keeps failing is only recorded once
+    launch_error: Option<String>,
+}
+
+/// segment that has been recorded and possibly compressed
+struct ClosedSegment {
+    /// name in the manifest
+    file_name: String,
+    /// where the segment was recorded to
+    path: PathBuf,
+    /// where the segment ends up after compression (same as path if it is not compressed)
+    compressed_path: PathBuf,
+    /// compression still in progress
+    compression: Option<JoinHandle<Result<PathBuf, String>>>,
+}
+
+impl ClosedSegment {
+    fn size(&self) -> u64 {
+        let size = |path: &PathBuf| fs::metadata(path).map_or(0, |metadata| metadata.len());
+        match self.compression {
+            // the segment stays on disk next to the compressed data written so far until compression is done
+            Some(_) => size(&self.path) + size(&self.compressed_path),
+            None => size(&self.compressed_path),
+        }
+    }
+}
+
+/// Records CSI into a session directory until interrupted, in segments that are rotated by time or size.
+/// Closed segments are optionally compressed, and the oldest ones deleted to stay below a disk usage cap.
+pub fn record(
+    parameters: &CollectionParameters,
+    options: &RecordOptions,
+    output: PathBuf,
+    metadata: &SessionMetadata,
+) -> Result<(), String> {
+    println!("Recording CSI until interrupted (Ctrl+C)...");
+
+    // finish the current segment and the manifest before exiting
+    let stop_requested = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        flag::register(signal, Arc::clone(&stop_requested))
+            .map_err(|err| format!("Could not register signal handler. Error: {}", err))?;
+    }
+
+    // stop CSI collection if it is running (may be running with other parameters)
+    if is_running() {
+        stop()?;
+    }
+
+    let mut session_parameters = parameters_json(parameters);
+    session_parameters["rotate_every_seconds"] = options
+        .rotate_every
+        .map(|rotate_every| rotate_every.as_secs_f64())
+        .into();
+    session_parameters["rotate_size_bytes"] = options.rotate_size.into();
+    session_parameters["compression"] = options
+        .compress
+        .map(|compression| compression.extension())
+        .into();
+    session_parameters["max_disk_bytes"] = options.max_disk.into();
+    let mut session = Session::create(&output, "record", session_parameters, metadata)?;
+
+    start(parameters)?;
+
+    let nexmon_socket = UdpSocket::bind("127.0.0.1:4400")
+        .map_err(|err| format!("Could not bind to port 4400. Error: {}", err))?;
+    nexmon_socket
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .map_err(|err| format!("Could not set read timeout. Error: {}", err))?;
+
+    // the tracker lives across segments and decoder restarts, so no gap goes unnoticed
+    let mut sequence_tracker = SequenceTracker::default();
+    let mut closed_segments = VecDeque::new();
+    let mut segment_number = 1;
+    let mut segment = Segment::open(&session, segment_number)?;
+
+    let decoder_pid = running_decoder_pid()?;
+    let mut decoder = DecoderWatch {
+        pid: decoder_pid,
+        arguments: decoder_pid.and_then(decoder_arguments),
+        launch_error: None,
+    };
+    let mut last_decoder_check = Instant::now();
+
+    while !stop_requested.load(Ordering::Relaxed) {
+        if last_decoder_check.elapsed() >= DECODER_CHECK_INTERVAL {
+            check_decoder(&mut session, &mut decoder)?;
+            last_decoder_check = Instant::now();
+        }
+
+        if segment.is_due(options) {
+            close_segment(
+                &mut session,
+                segment,
+                &mut sequence_tracker,
+                options,
+                &mut closed_segments,
+            )?;
+            segment_number += 1;
+            segment = Segment::open(&session, segment_number)?;
+        }
+
+        let mut packet = [0; 8192];
+        let packet_len = match nexmon_socket.recv_from(&mut packet) {
+            Ok((packet_len, _)) => packet_len,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                continue
+            }
+            // interrupted by a signal
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(format!("Could not receive from port 4400. Error: {}", err)),
+        };
+
+        segment.capture_writer.write(&packet[..packet_len])?;
+        segment.frames += 1;
+        if let Ok(nexmon_data) = NexmonData::decode(&packet[..packet_len]) {
+            sequence_tracker.track(
+                nexmon_data.source_mac,
+                nexmon_data.seq_num,
+                nexmon_data.core,
+                nexmon_data.spatial_stream,
+            );
+        }
+    }
+
+    println!("Finishing recording...");
+    close_segment(
+        &mut session,
+        segment,
+        &mut sequence_tracker,
+        options,
+        &mut closed_segments,
+    )?;
+    finish_compressions(&mut session, &mut closed_segments, true)?;
+
+    stop()?;
+
+    println!(
+        "All done! Your recorded CSI is available in {}",
+        session.directory().display()
+    );
+    session.finish()?;
+
+    Ok(())
+}
+
+/// records the segment in the manifest, starts compressing it and enforces the disk usage cap
+fn close_segment(
+    session: &mut Session,
+    segment: Segment,
+    sequence_tracker: &mut SequenceTracker,
+    options: &RecordOptions,
+    closed_segments: &mut VecDeque<ClosedSegment>,
+) -> Result<(), String> {
+    let Segment {
+        name,
+        path,
+        capture_writer,
+        frames,
+        ..
+    } = segment;
+    drop(capture_writer);
+
+    let file_name = match options.compress {
+        Some(compression) => format!("{}.{}", name, compression.extension()),
+        None => name,
+    };
+    session.add_file(
+        &file_name,
+        frames,
+        Some(&sequence_tracker.take()),
+        None,
+        None,
+    )?;
+    println!("Closed {} ({} frames)", file_name, frames);
+
+    finish_compressions(session, closed_segments, false)?;
+    let compression = options.compress.map(|compression| {
+        let raw_path = path.clone();
+        thread::spawn(move || compress_file(&raw_path, compression))
+    });
+    closed_segments.push_back(ClosedSegment {
+        compressed_path: session.path(&file_name),
+        file_name,
+        path,
+        compression,
+    });
+
+    if let Some(max_disk) = options.max_disk {
+        enforce_disk_cap(session, closed_segments, max_disk)?;
+    }
+
+    Ok(())
+}
+
+/// collects the compressions that are done (or waits for all of them), recording failures in the manifest
+fn finish_compressions(
+    session: &mut Session,
+    closed_segments: &mut VecDeque<ClosedSegment>,
+    wait: bool,
+) -> Result<(), String> {
+    for closed_segment in closed_segments.iter_mut() {
+        let compression = match closed_segment.compression.take() {
+            Some(compression) if wait || compression.is_finished() => compression,
+            compression => {
+                closed_segment.compression = compression;
+                continue;
+            }
+        };
+        let err = match compression.join() {
+            Ok(Ok(_)) => continue,
+            Ok(Err(err)) => err,
+            Err(_) => format!("Compression of {} failed", closed_segment.file_name),
+        };
+        println!("{}", err);
+        session.add_event(&err)?;
+
+        // the segment is kept uncompressed, so the manifest has to list it under its own name
+        let raw_name = match closed_segment.path.file_name() {
+            Some(raw_name) => raw_name.to_string_lossy().into_owned(),
+            None => continue,
+        };
+        session.rename_file(&closed_segment.file_name, &raw_name)?;
+        closed_segment.file_name = raw_name;
+        closed_segment.compressed_path = closed_segment.path.clone();
+    }
+    Ok(())
+}
+
+/// deletes the oldest closed segments (but never the newest) while they take up more than `max_disk` bytes
+fn enforce_disk_cap(
+    session: &mut Session,
+    closed_segments: &mut VecDeque<ClosedSegment>,
+    max_disk: u64,
+) -> Result<(), String> {
+    let mut usage: u64 = closed_segments
+        .iter()
+        .map(|closed_segment| closed_segment.size())
+        .sum();
+    while usage > max_disk && closed_segments.len() > 1 {
+        let mut oldest = match closed_segments.pop_front() {
+            Some(oldest) => oldest,
+            None => break,
+        };
+        usage -= oldest.size().min(usage);
+        // whether or not compressing it works out, only one of its files is left afterwards
+        if let Some(compression) = oldest.compression.take() {
+            let _ = compression.join();
+        }
+        remove_file(&oldest.compressed_path)
+            .or_else(|_| remove_file(&oldest.path))
+            .map_err(|err| format!("Could not delete {}. Error: {}", oldest.file_name, err))?;
+        println!(
+            "Deleted {} to stay below the disk usage cap",
+            oldest.file_name
+        );
+        session.mark_deleted(&oldest.file_name)?;
+    }
+
+    Ok(())
+}
+
+/// relaunches the decoder if it stopped and records restarts in the manifest. A failed relaunch is recorded and tried
+/// again on the next check rather than ending the recording.
+fn check_decoder(session: &mut Session, decoder: &mut DecoderWatch) -> Result<(), String> {
+    let current_pid = running_decoder_pid()?;
+    let message = match (decoder.pid, current_pid) {
+        // systemd restarts the decoder of cspi-decoder.service itself
+        (_, None) if decoder_unit_active() => return Ok(()),
+        (_, None) => {
+            let launched = match &decoder.arguments {
+                Some(arguments) => relaunch_decoder(arguments),
+                None => launch_decoder(&DecoderOptions::default()),
+            };
+            match launched {
+                Ok(()) => {
+                    decoder.launch_error = None;
+                    String::from("Decoder was not running, launched it")
+                }
+                Err(err) if decoder.launch_error.as_ref() == Some(&err) => return Ok(()),
+                Err(err) => {
+                    decoder.launch_error = Some(err.clone());
+                    format!("Could not launch the decoder, trying again: {}", err)
+                }
+            }
+        }
+        (None, Some(pid)) => format!("Decoder running again with PID {}", pid),
+        (Some(previous), Some(pid)) if previous != pid => {
+            format!("Decoder restarted, PID {} instead of {}", pid, previous)
+        }
+        _ => return Ok(()),
+    };
+    if let Some(arguments) = current_pid.and_then(decoder_arguments) {
+        decoder.arguments = Some(arguments);
+    }
+    decoder.pid = current_pid;
+
+    println!("{}", message);
+    session.add_event(&message)
+}
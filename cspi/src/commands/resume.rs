@@ -0,0 +1,78 @@
+use std::env;
+use std::fs::{remove_file, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::commands::{
+    running::{is_running, RunningState},
+    start::start,
+    systemd::systemctl,
+};
+
+const RESUME_UNIT_STR: &str = "/etc/systemd/system/cspi-resume.service";
+
+/// restarts CSI collection with the parameters it was running with before the last reboot
+pub fn resume() -> Result<(), String> {
+    let running_state = match RunningState::load()? {
+        Some(running_state) => running_state,
+        None => {
+            println!("CSI collection was not running before, nothing to resume.");
+            return Ok(());
+        }
+    };
+
+    if is_running() {
+        println!("CSI collection is already running, nothing to resume.");
+        return Ok(());
+    }
+
+    println!("Resuming CSI collection...");
+    start(&running_state.parameters)
+}
+
+/// installs and enables a systemd unit that resumes CSI collection at boot
+pub fn enable_resume_on_boot() -> Result<(), String> {
+    let executable = env::current_exe()
+        .map_err(|err| format!("Could not determine path of cspi binary. Error: {}", err))?;
+
+    let mut unit_file = File::create(RESUME_UNIT_STR)
+        .map_err(|err| format!("Could not create {}. Error: {}", RESUME_UNIT_STR, err))?;
+    write!(
+        unit_file,
+        "[Unit]\n\
+         Description=Resume CSI collection after reboot\n\
+         After=network.target\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         ExecStart={} resume\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        executable.display()
+    )
+    .map_err(|err| format!("Could not write {}. Error: {}", RESUME_UNIT_STR, err))?;
+
+    systemctl(&["daemon-reload"])?;
+    systemctl(&["enable", "cspi-resume.service"])?;
+
+    println!("CSI collection will be resumed after every reboot.");
+
+    Ok(())
+}
+
+/// disables and removes the boot unit
+pub fn disable_resume_on_boot() -> Result<(), String> {
+    if !PathBuf::from(RESUME_UNIT_STR).exists() {
+        return Err(String::from("Resuming on boot is not enabled"));
+    }
+
+    systemctl(&["disable", "cspi-resume.service"])?;
+    remove_file(RESUME_UNIT_STR)
+        .map_err(|err| format!("Could not remove {}. Error: {}", RESUME_UNIT_STR, err))?;
+    systemctl(&["daemon-reload"])?;
+
+    println!("CSI collection will no longer be resumed after a reboot.");
+
+    Ok(())
+}
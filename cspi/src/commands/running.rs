@@ -1,10 +1,152 @@
+use std::fs::{self, File};
+use std::io::Write;
 use std::path::PathBuf;
 
 use super::NEXMON_RUNNING_STR;
+use crate::channel::Channel;
+use crate::csi::FrameType;
+use crate::frame::{frame_type_name, parse_frame_types};
+use crate::mac::MacFilter;
+
+const BOOT_ID_STR: &str = "/proc/sys/kernel/random/boot_id";
+
+/// parameters CSI collection is started with
+#[derive(Clone)]
+pub struct CollectionParameters {
+    pub channel: Channel,
+    /// only frames from these sources are evaluated (all if empty)
+    pub maclist: MacFilter,
+    /// only frames of these types are evaluated (all if empty)
+    pub frame_types: Vec<FrameType>,
+    /// bitmask of the cores (receive antennas) CSI is collected on
+    pub cores: u8,
+    /// bitmask of the spatial streams CSI is collected of
+    pub streams: u8,
+}
+
+impl CollectionParameters {
+    /// frames measured per transmission, one per core and spatial stream
+    pub fn chains(&self) -> usize {
+        (self.cores.count_ones() * self.streams.count_ones()) as usize
+    }
+}
+
+/// contents of the running marker: the parameters collection was last started with
+pub struct RunningState {
+    pub parameters: CollectionParameters,
+    /// boot the parameters were applied in, collection does not survive a reboot
+    boot_id: Option<String>,
+}
+
+impl RunningState {
+    /// saves the parameters as the running marker
+    pub fn save(parameters: &CollectionParameters) -> Result<(), String> {
+        let mut file = File::create(NEXMON_RUNNING_STR)
+            .map_err(|err| format!("Could not save running state. Error: {}", err))?;
+
+        let mut contents = format!(
+            "channel={}\nbandwidth={}\nmaclist={}\nframe_types={}\ncores={:#x}\nstreams={:#x}\n",
+            parameters.channel,
+            parameters.channel.bandwidth,
+            parameters.maclist,
+            parameters
+                .frame_types
+                .iter()
+                .map(|frame_type| frame_type_name(*frame_type))
+                .collect::<Vec<String>>()
+                .join(","),
+            parameters.cores,
+            parameters.streams
+        );
+        if let Some(boot_id) = current_boot_id() {
+            contents += &format!("boot_id={}\n", boot_id);
+        }
+
+        file.write_all(contents.as_bytes())
+            .map_err(|err| format!("Could not save running state. Error: {}", err))
+    }
+
+    /// reads the running marker, if there is one
+    pub fn load() -> Result<Option<RunningState>, String> {
+        let contents = match fs::read_to_string(NEXMON_RUNNING_STR) {
+            Ok(contents) => contents,
+            Err(_) => return Ok(None),
+        };
+
+        let mut channel = None;
+        let mut bandwidth = None;
+        let mut maclist = MacFilter::default();
+        let mut frame_types = vec![];
+        // collection used to be limited to the first core and spatial stream
+        let mut cores = 0x1;
+        let mut streams = 0x1;
+        let mut boot_id = None;
+
+        for line in contents.lines() {
+            let (key, value) = match line.split_once('=') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            match key {
+                "channel" => channel = Some(value),
+                "bandwidth" => bandwidth = value.parse().ok(),
+                "maclist" => maclist = MacFilter::parse(value)?,
+                "frame_types" => frame_types = parse_frame_types(value)?,
+                "cores" => cores = parse_hex(value).unwrap_or(cores),
+                "streams" => streams = parse_hex(value).unwrap_or(streams),
+                "boot_id" => boot_id = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+
+        match (channel, bandwidth) {
+            (Some(channel), Some(bandwidth)) => Ok(Some(RunningState {
+                parameters: CollectionParameters {
+                    channel: Channel::parse(channel, bandwidth)?,
+                    maclist,
+                    frame_types,
+                    cores,
+                    streams,
+                },
+                boot_id,
+            })),
+            _ => Err(String::from(
+                "The running state does not contain any collection parameters (it was likely saved by an older version of cspi)",
+            )),
+        }
+    }
+
+    /// whether the parameters were applied before the last reboot
+    pub fn is_stale(&self) -> bool {
+        match (&self.boot_id, current_boot_id()) {
+            (Some(saved), Some(current)) => *saved != current,
+            _ => false,
+        }
+    }
+}
 
 pub fn running() {
     match is_running() {
-        true => println!("CSI collection is currently running."),
+        true => match RunningState::load() {
+            Ok(Some(RunningState { parameters, .. })) => println!(
+                "CSI collection is currently running on channel {} from {}{}.",
+                parameters.channel.describe(),
+                parameters.maclist.describe(),
+                match parameters.frame_types.is_empty() {
+                    true => String::new(),
+                    false => format!(
+                        " for frame types {}",
+                        parameters
+                            .frame_types
+                            .iter()
+                            .map(|frame_type| frame_type_name(*frame_type))
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    ),
+                }
+            ),
+            _ => println!("CSI collection is currently running."),
+        },
         false => println!("CSI collection is not running."),
     }
 }
@@ -12,6 +154,19 @@ pub fn running() {
 pub fn is_running() -> bool {
     let is_running_path = PathBuf::from(NEXMON_RUNNING_STR);
 
-    // Check if nexmon_csi has already been installed
-    is_running_path.exists()
+    // Check if nexmon_csi has already been started (in this boot)
+    match RunningState::load() {
+        Ok(Some(running_state)) => !running_state.is_stale(),
+        _ => is_running_path.exists(),
+    }
+}
+
+fn parse_hex(value: &str) -> Option<u8> {
+    u8::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+fn current_boot_id() -> Option<String> {
+    fs::read_to_string(BOOT_ID_STR)
+        .ok()
+        .map(|boot_id| boot_id.trim().to_owned())
 }
@@ -0,0 +1,300 @@
+use prost::Message;
+use serde_json::json;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::capture::CaptureReader;
+use crate::commands::detect::{CsiStream, EventSink, OutputTarget};
+use crate::csi::{NexmonData, Subscription};
+use crate::mac::MacFilter;
+use crate::processing::doppler::{DopplerSpectrogram, SpectrogramColumn, StftSettings};
+
+/// pauses in images are shortened to this many steps, so a long break does not make the image arbitrarily wide
+const MAX_GAP_STEPS: usize = 10;
+/// range in dB below the strongest bin that is shown in images, anything weaker gets the weakest color
+const DYNAMIC_RANGE: f32 = 40.0;
+/// colors (viridis) from weak to strong, interpolated in between
+const COLORMAP: [[f32; 3]; 5] = [
+    [68.0, 1.0, 84.0],
+    [59.0, 82.0, 139.0],
+    [33.0, 145.0, 140.0],
+    [94.0, 201.0, 98.0],
+    [253.0, 231.0, 37.0],
+];
+
+/// settings of spectrogram computation
+pub struct SpectrogramOptions {
+    /// capture to compute the spectrogram of, CSI from the decoder if None
+    pub input: Option<PathBuf>,
+    pub source: MacFilter,
+    pub settings: StftSettings,
+    /// .npy or .png file the whole spectrogram is written to at the end
+    pub save: Option<PathBuf>,
+    pub outputs: Vec<OutputTarget>,
+}
+
+/// where the CSI comes from
+enum CsiSource {
+    Live(CsiStream),
+    Capture {
+        reader: CaptureReader,
+        source: MacFilter,
+        finished: bool,
+    },
+}
+
+impl CsiSource {
+    /// next frame, None if none arrived for a moment or the frame is not from the source
+    fn next(&mut self) -> Result<Option<NexmonData>, String> {
+        match self {
+            CsiSource::Live(stream) => stream.next(),
+            CsiSource::Capture {
+                reader,
+                source,
+                finished,
+            } => {
+                let message = match reader.next_message()? {
+                    Some(message) => message,
+                    None => {
+                        *finished = true;
+                        return Ok(None);
+                    }
+                };
+                let nexmon_data = match NexmonData::decode(message.as_slice()) {
+                    Ok(nexmon_data) if source.matches(nexmon_data.source_mac) => nexmon_data,
+                    _ => return Ok(None),
+                };
+                if nexmon_data.timestamp == 0 {
+                    return Err(String::from(
+                        "The capture has no receive timestamps, it was collected before the decoder recorded them",
+                    ));
+                }
+                Ok(Some(nexmon_data))
+            }
+        }
+    }
+
+    fn finished(&self) -> bool {
+        matches!(self, CsiSource::Capture { finished: true, .. })
+    }
+}
+
+/// Computes the Doppler spectrogram of a transmitter, live until interrupted or of a whole capture.
+/// Every column is streamed to the outputs, the whole spectrogram can be saved at the end.
+pub fn spectrogram(options: SpectrogramOptions) -> Result<(), String> {
+    let stop_requested = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        flag::register(signal, Arc::clone(&stop_requested))
+            .map_err(|err| format!("Could not register signal handler. Error: {}", err))?;
+    }
+
+    let mut source = match &options.input {
+        Some(path) => CsiSource::Capture {
+            reader: CaptureReader::open(path)?,
+            source: options.source.clone(),
+            finished: false,
+        },
+        // normalized CSI, so gain control does not show up as movement
+        None => CsiSource::Live(CsiStream::open(
+            options.source.clone(),
+            Subscription {
+                normalize: true,
+                ..Subscription::default()
+            },
+        )?),
+    };
+    // columns only go to stdout by default if the spectrogram is not saved
+    let mut sink = match (options.outputs.is_empty(), &options.save) {
+        (true, Some(_)) => None,
+        _ => Some(EventSink::open(&options.outputs)?),
+    };
+    let step = options.settings.step;
+    let mut spectrogram = DopplerSpectrogram::new(options.settings);
+    let mut columns: Vec<SpectrogramColumn> = vec![];
+
+    while !stop_requested.load(Ordering::Relaxed) && !source.finished() {
+        if let Some(nexmon_data) = source.next()? {
+            spectrogram.add(&nexmon_data);
+        }
+        let new_columns = spectrogram.columns();
+        if let Some(sink) = sink.as_mut() {
+            sink.keep_alive();
+            for column in new_columns.iter() {
+                sink.emit(&json!({
+                    "time": format_time(column.time),
+                    "event": "spectrogram",
+                    "link": spectrogram.link(),
+                    "frequencies": spectrogram.frequencies(),
+                    "groups": spectrogram.groups(),
+                    "power": column.power,
+                }));
+            }
+        }
+        if options.save.is_some() {
+            columns.extend(new_columns);
+        }
+    }
+
+    if let Some(path) = &options.save {
+        if columns.is_empty() {
+            return Err(String::from(
+                "No spectrogram to save, not enough CSI was received from the transmitter",
+            ));
+        }
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("npy") => save_array(path, &spectrogram, &columns)?,
+            Some("png") => save_image(path, &columns, step)?,
+            _ => {
+                return Err(format!(
+                    "Can't save spectrogram as {} (expected a .npy or .png file)",
+                    path.display()
+                ))
+            }
+        }
+        eprintln!(
+            "Saved spectrogram of {} columns to {}",
+            columns.len(),
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+fn format_time(time_us: u64) -> String {
+    humantime::format_rfc3339_millis(UNIX_EPOCH + Duration::from_micros(time_us)).to_string()
+}
+
+/// writes the power as float32 array (columns × groups × frequencies) and its axes as JSON next to it
+fn save_array(
+    path: &Path,
+    spectrogram: &DopplerSpectrogram,
+    columns: &[SpectrogramColumn],
+) -> Result<(), String> {
+    // columns from before the bandwidth last changed can't be part of the same array
+    let (groups, frequencies) = dimensions(columns)?;
+    let columns: Vec<&SpectrogramColumn> = columns
+        .iter()
+        .filter(|column| column.power.len() == groups)
+        .collect();
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}, {}), }}",
+        columns.len(),
+        groups,
+        frequencies
+    );
+    // the header is padded so the data starts at a multiple of 64 bytes
+    while (10 + header.len() + 1) % 64 != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+
+    let write = || -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(b"\x93NUMPY\x01\x00")?;
+        file.write_all(&(header.len() as u16).to_le_bytes())?;
+        file.write_all(header.as_bytes())?;
+        for power in columns
+            .iter()
+            .flat_map(|column| column.power.iter().flatten())
+        {
+            file.write_all(&power.to_le_bytes())?;
+        }
+        file.flush()
+    };
+    write().map_err(|err| format!("Could not write {}. Error: {}", path.display(), err))?;
+
+    let first = columns[0].time;
+    let axes = json!({
+        "start": format_time(first),
+        "link": spectrogram.link(),
+        "times": columns
+            .iter()
+            .map(|column| (column.time - first) as f64 / 1_000_000.0)
+            .collect::<Vec<f64>>(),
+        "frequencies": spectrogram.frequencies(),
+        "groups": spectrogram.groups(),
+    });
+    let axes_path = path.with_extension("json");
+    fs::write(&axes_path, axes.to_string())
+        .map_err(|err| format!("Could not write {}. Error: {}", axes_path.display(), err))
+}
+
+/// writes an image with a pixel per step from left to right and frequency from bottom to top, one band per subcarrier group
+fn save_image(path: &Path, columns: &[SpectrogramColumn], step_us: u64) -> Result<(), String> {
+    // gaps and columns from before the bandwidth last changed are left black
+    let (groups, frequencies) = dimensions(columns)?;
+    let mut positions = Vec::with_capacity(columns.len());
+    let mut x = 0;
+    for (index, column) in columns.iter().enumerate() {
+        if index > 0 {
+            let steps = (column.time.saturating_sub(columns[index - 1].time) as f64
+                / step_us.max(1) as f64)
+                .round() as usize;
+            x += steps.clamp(1, MAX_GAP_STEPS);
+        }
+        positions.push(x);
+    }
+    let width = x + 1;
+    // a black line between groups
+    let height = groups * (frequencies + 1) - 1;
+    let strongest = columns
+        .iter()
+        .flat_map(|column| column.power.iter().flatten())
+        .fold(f32::MIN, |strongest, power| strongest.max(*power));
+
+    let mut pixels = vec![0; width * height * 3];
+    for (column, x) in columns.iter().zip(positions) {
+        if column.power.len() != groups {
+            continue;
+        }
+        for (group, power) in column.power.iter().enumerate() {
+            for (frequency, power) in power.iter().enumerate() {
+                let y = group * (frequencies + 1) + (frequencies - 1 - frequency);
+                let offset = (y * width + x) * 3;
+                let level = ((power - strongest + DYNAMIC_RANGE) / DYNAMIC_RANGE).clamp(0.0, 1.0);
+                pixels[offset..offset + 3].copy_from_slice(&color(level));
+            }
+        }
+    }
+
+    let write = || -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(File::create(path)?),
+            width as u32,
+            height as u32,
+        );
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&pixels)
+    };
+    write().map_err(|err| format!("Could not write {}. Error: {}", path.display(), err))
+}
+
+/// number of subcarrier groups and frequencies of the newest columns
+fn dimensions(columns: &[SpectrogramColumn]) -> Result<(usize, usize), String> {
+    let power = columns
+        .last()
+        .map_or(&[][..], |column| column.power.as_slice());
+    match power.first().map(|frequencies| frequencies.len()) {
+        Some(frequencies) if frequencies > 0 => Ok((power.len(), frequencies)),
+        _ => Err(String::from(
+            "No spectrogram to save, the columns hold no frequencies",
+        )),
+    }
+}
+
+/// color of a level from 0 (weak) to 1 (strong)
+fn color(level: f32) -> [u8; 3] {
+    let position = level * (COLORMAP.len() - 1) as f32;
+    let index = (position as usize).min(COLORMAP.len() - 2);
+    let fraction = position - index as f32;
+    let [low, high] = [COLORMAP[index], COLORMAP[index + 1]];
+    [0, 1, 2].map(|channel| (low[channel] + (high[channel] - low[channel]) * fraction) as u8)
+}
@@ -1,10 +1,13 @@
-use std::{fs::File, process::Command};
+use std::process::Command;
 
 use crate::commands::{
-    apply::is_applied, decode::launch_decoder, running::is_running, NEXMON_RUNNING_STR,
+    apply::is_applied,
+    decode::{launch_decoder, DecoderOptions},
+    running::{is_running, CollectionParameters, RunningState},
 };
+use crate::frame::first_byte;
 
-pub fn start(channel: &u32, bandwidth: &u32, maclist: &String) -> Result<(), String> {
+pub fn start(parameters: &CollectionParameters) -> Result<(), String> {
     println!("Starting CSI collection...");
 
     // check whether patch has been applied
@@ -17,14 +20,31 @@ pub fn start(channel: &u32, bandwidth: &u32, maclist: &String) -> Result<(), Str
         return Err(String::from("CSI collection is already running"));
     }
 
-    let channel_bandwidth = format!("{}/{}", channel, bandwidth);
-    let mut arglist = vec!["-C", "1", "-N", "1", "-c", &channel_bandwidth];
-    if !maclist.is_empty() {
+    let channel_bandwidth = parameters.channel.to_string();
+    let cores = format!("{:#x}", parameters.cores);
+    let streams = format!("{:#x}", parameters.streams);
+    let mut arglist = vec!["-C", &cores, "-N", &streams, "-c", &channel_bandwidth];
+
+    // the firmware can filter on a few complete addresses, the decoder filters everything else
+    let firmware_maclist = parameters.maclist.firmware_maclist();
+    if let Some(maclist) = firmware_maclist
+        .as_ref()
+        .filter(|maclist| !maclist.is_empty())
+    {
         arglist.extend(["-m", maclist]);
     }
 
+    // the firmware can filter on a single frame type, the decoder filters the rest
+    let first_byte = match parameters.frame_types[..] {
+        [frame_type] => first_byte(frame_type).map(|byte| format!("0x{:02x}", byte)),
+        _ => None,
+    };
+    if let Some(first_byte) = &first_byte {
+        arglist.extend(["-b", first_byte]);
+    }
+
     // create parameter string
-    let parameters = String::from_utf8(
+    let csi_parameters = String::from_utf8(
         Command::new("mcp")
             .args(arglist)
             .output()
@@ -40,7 +60,7 @@ pub fn start(channel: &u32, bandwidth: &u32, maclist: &String) -> Result<(), Str
         .map_err(|err| format!("Error running ifconfig wlan0 up: {}", err))?;
     Command::new("nexutil")
         .args(["-Iwlan0", "-s500", "-b", "-l34"])
-        .arg(format!("-v{}", parameters))
+        .arg(format!("-v{}", csi_parameters))
         .status()
         .map_err(|err| format!("Error using nexutil to set up nexmon: {}", err))?;
 
@@ -81,12 +101,13 @@ pub fn start(channel: &u32, bandwidth: &u32, maclist: &String) -> Result<(), Str
         .map_err(|err| format!("Error enabling mon0: {}", err))?;
 
     // Launch decoder in background if not running
-    let _ = launch_decoder();
+    let _ = launch_decoder(&DecoderOptions::default());
 
-    // Remember running state
-    File::create(NEXMON_RUNNING_STR)
-        .map_err(|err| format!("Could not save running state. Error: {}", err))?;
+    // Remember running state and parameters
+    RunningState::save(parameters)?;
 
+    println!("Collecting on channel {}.", parameters.channel.describe());
+    println!("Evaluating CSI from {}.", parameters.maclist.describe());
     println!("CSI collection is running.\nCSI in nexmon format is available on port 5500.\nCSI in protobuf format is available on port 4400.");
 
     Ok(())
@@ -1,6 +1,10 @@
-use std::{process::Command, fs::remove_file};
+use std::{fs::remove_file, process::Command};
 
-use crate::commands::{apply::is_applied, NEXMON_RUNNING_STR, running::is_running};
+use crate::commands::{
+    apply::is_applied,
+    running::{is_running, RunningState},
+    NEXMON_RUNNING_STR,
+};
 
 pub fn stop() -> Result<(), String> {
     println!("Stopping CSI collection...");
@@ -10,6 +14,16 @@ pub fn stop() -> Result<(), String> {
         return Err(String::from("You must apply the firmware patch using cspi apply before starting or stopping collection!"));
     }
 
+    // collection started before the last reboot is no longer running, only forget it so it isn't resumed
+    if let Ok(Some(running_state)) = RunningState::load() {
+        if running_state.is_stale() {
+            remove_file(NEXMON_RUNNING_STR)
+                .map_err(|err| format!("Could not save running state. Error: {}", err))?;
+            println!("CSI collection has not been running since the last reboot and will not be resumed.");
+            return Ok(());
+        }
+    }
+
     // check whether CSI collection is running
     if !is_running() {
         return Err(String::from("CSI collection is not running, cannot stop"));
@@ -0,0 +1,141 @@
+use prost::Message;
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::capture::CaptureWriter;
+use crate::channel::Channel;
+use crate::commands::running::{is_running, CollectionParameters};
+use crate::commands::start::start;
+use crate::commands::stop::stop;
+use crate::csi::NexmonData;
+use crate::sequence::SequenceTracker;
+use crate::session::{parameters_json, Session, SessionMetadata};
+
+/// Collects CSI on every channel for `dwell`, going through all channels `cycles` times.
+/// The CSI of every dwell is written to its own file in the session directory.
+pub fn sweep(
+    sweep_parameters: &[CollectionParameters],
+    dwell: &Duration,
+    cycles: &u32,
+    output: PathBuf,
+    metadata: &SessionMetadata,
+) -> Result<(), String> {
+    if sweep_parameters.is_empty() {
+        return Err(String::from("No channels to sweep"));
+    }
+
+    println!(
+        "Sweeping {} channels {} times, {} on every channel...",
+        sweep_parameters.len(),
+        cycles,
+        humantime::format_duration(*dwell)
+    );
+
+    // all channels share the filters, so they are stored once
+    let mut session_parameters = parameters_json(&sweep_parameters[0]);
+    session_parameters["channel"] = sweep_parameters
+        .iter()
+        .map(|parameters| parameters.channel.to_string())
+        .collect::<Vec<String>>()
+        .into();
+    session_parameters["dwell_seconds"] = dwell.as_secs_f64().into();
+    session_parameters["cycles"] = (*cycles).into();
+    let mut session = Session::create(&output, "sweep", session_parameters, metadata)?;
+
+    let nexmon_socket = UdpSocket::bind("127.0.0.1:4400")
+        .map_err(|err| format!("Could not bind to port 4400. Error: {}", err))?;
+    nexmon_socket
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .map_err(|err| format!("Could not set read timeout. Error: {}", err))?;
+
+    for cycle in 1..=*cycles {
+        for parameters in sweep_parameters {
+            // reconfigure the firmware for the next channel
+            if is_running() {
+                stop()?;
+            }
+            start(parameters)?;
+
+            let file_name = format!(
+                "cycle{}_{}.csi",
+                cycle,
+                parameters.channel.to_string().replace('/', "-")
+            );
+            let path = session.path(&file_name);
+            let (frames, sequence_tracker) =
+                dwell_on(&nexmon_socket, &parameters.channel, dwell, &path)?;
+            println!(
+                "Cycle {} of {}, channel {}: {} frames written to {}",
+                cycle,
+                cycles,
+                parameters.channel,
+                frames,
+                path.display()
+            );
+            session.add_file(
+                &file_name,
+                frames,
+                Some(&sequence_tracker),
+                Some(parameters.channel.to_string()),
+                Some(cycle),
+            )?;
+        }
+    }
+
+    stop()?;
+
+    println!(
+        "All done! Your collected CSI is available in {}",
+        session.directory().display()
+    );
+    session.finish()?;
+
+    Ok(())
+}
+
+/// writes the CSI received on a channel for the dwell time to a capture, returns the number of frames and their loss statistics
+fn dwell_on(
+    nexmon_socket: &UdpSocket,
+    channel: &Channel,
+    dwell: &Duration,
+    path: &Path,
+) -> Result<(u64, SequenceTracker), String> {
+    let mut capture_writer = CaptureWriter::create(path)?;
+    let mut frames = 0;
+    let mut sequence_tracker = SequenceTracker::default();
+
+    let until = Instant::now() + *dwell;
+    while Instant::now() < until {
+        let mut packet = [0; 8192];
+        let packet_len = match nexmon_socket.recv_from(&mut packet) {
+            Ok((packet_len, _)) => packet_len,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                continue
+            }
+            Err(err) => return Err(format!("Could not receive from port 4400. Error: {}", err)),
+        };
+
+        // frames still arriving from the previous channel don't belong into this dwell
+        if let Ok(nexmon_data) = NexmonData::decode(&packet[..packet_len]) {
+            if nexmon_data.channel != 0
+                && (nexmon_data.channel, nexmon_data.bandwidth)
+                    != (channel.control, channel.bandwidth)
+            {
+                continue;
+            }
+            sequence_tracker.track(
+                nexmon_data.source_mac,
+                nexmon_data.seq_num,
+                nexmon_data.core,
+                nexmon_data.spatial_stream,
+            );
+        }
+
+        capture_writer.write(&packet[..packet_len])?;
+        frames += 1;
+    }
+
+    Ok((frames, sequence_tracker))
+}
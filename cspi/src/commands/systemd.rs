@@ -0,0 +1,213 @@
+use std::env;
+use std::fs::{self, create_dir_all, remove_file};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use crate::commands::running::CollectionParameters;
+use crate::frame::frame_type_name;
+
+const SYSTEMD_UNIT_PATH_STR: &str = "/etc/systemd/system/";
+const DECODER_UNIT: &str = "cspi-decoder.service";
+const COLLECT_UNIT: &str = "cspi-collect@.service";
+/// one environment file per cspi-collect@ instance, containing CHANNEL, BANDWIDTH, MACLIST, FRAME_TYPES, CORES and STREAMS
+const COLLECT_CONFIG_PATH_STR: &str = "/home/pi/.cspi/collect/";
+
+/// generates the decoder and collection units, then enables and starts the decoder
+pub fn systemd_install() -> Result<(), String> {
+    println!("Installing systemd units...");
+
+    let executable = env::current_exe()
+        .map_err(|err| format!("Could not determine path of cspi binary. Error: {}", err))?;
+    let executable = executable.display();
+
+    let decoder_unit = format!(
+        "[Unit]\n\
+         Description=cspi CSI decoder (nexmon format on port 5500 to protobuf on port 4400)\n\
+         After=network.target\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart={executable} decode --foreground\n\
+         WatchdogSec=30\n\
+         Restart=on-failure\n\
+         StandardOutput=journal\n\
+         StandardError=journal\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n"
+    );
+
+    let collect_unit = format!(
+        "[Unit]\n\
+         Description=cspi CSI collection (%i)\n\
+         Requires={DECODER_UNIT}\n\
+         After={DECODER_UNIT}\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         RemainAfterExit=yes\n\
+         Environment=CORES=0x1 STREAMS=0x1\n\
+         EnvironmentFile={COLLECT_CONFIG_PATH_STR}%i.conf\n\
+         ExecStart={executable} start -c ${{CHANNEL}} -b ${{BANDWIDTH}} -m ${{MACLIST}} -f ${{FRAME_TYPES}} -C ${{CORES}} -N ${{STREAMS}}\n\
+         ExecStop={executable} stop\n\
+         StandardOutput=journal\n\
+         StandardError=journal\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n"
+    );
+
+    write_unit(DECODER_UNIT, &decoder_unit)?;
+    write_unit(COLLECT_UNIT, &collect_unit)?;
+
+    systemctl(&["daemon-reload"])?;
+    systemctl(&["enable", "--now", DECODER_UNIT])?;
+
+    println!(
+        "Installed {} (enabled and started) and {}.",
+        DECODER_UNIT, COLLECT_UNIT
+    );
+    println!("Use 'sudo cspi systemd collect <name> -c <channel> -b <bandwidth> -m <maclist>' to collect CSI as a service.");
+
+    Ok(())
+}
+
+/// writes the configuration for a cspi-collect@ instance, then enables and starts it
+pub fn systemd_collect(name: &str, parameters: &CollectionParameters) -> Result<(), String> {
+    validate_instance_name(name)?;
+    if !is_installed(COLLECT_UNIT) {
+        return Err(String::from(
+            "The collection unit is not installed! Run 'sudo cspi systemd install' first.",
+        ));
+    }
+
+    create_dir_all(COLLECT_CONFIG_PATH_STR)
+        .map_err(|err| format!("Could not create configuration directory. Error: {}", err))?;
+    let config_path = PathBuf::from(format!("{}{}.conf", COLLECT_CONFIG_PATH_STR, name));
+    fs::write(
+        &config_path,
+        format!(
+            "CHANNEL={}\nBANDWIDTH={}\nMACLIST={}\nFRAME_TYPES={}\nCORES={:#x}\nSTREAMS={:#x}\n",
+            parameters.channel,
+            parameters.channel.bandwidth,
+            parameters.maclist,
+            parameters
+                .frame_types
+                .iter()
+                .map(|frame_type| frame_type_name(*frame_type))
+                .collect::<Vec<String>>()
+                .join(","),
+            parameters.cores,
+            parameters.streams
+        ),
+    )
+    .map_err(|err| format!("Could not write {}. Error: {}", config_path.display(), err))?;
+
+    let instance = format!("cspi-collect@{}.service", name);
+    systemctl(&["enable", "--now", &instance])?;
+
+    println!("CSI collection is running as {}.", instance);
+
+    Ok(())
+}
+
+/// stops, disables and removes all cspi units
+pub fn systemd_uninstall() -> Result<(), String> {
+    println!("Removing systemd units...");
+
+    // stop collection instances before the decoder they depend on, units that are not installed can't be disabled
+    if is_installed(COLLECT_UNIT) {
+        if let Ok(configs) = fs::read_dir(COLLECT_CONFIG_PATH_STR) {
+            for config in configs.flatten() {
+                let path = config.path();
+                if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                    systemctl(&[
+                        "disable",
+                        "--now",
+                        &format!("cspi-collect@{}.service", name),
+                    ])?;
+                }
+            }
+        }
+    }
+    if is_installed(DECODER_UNIT) {
+        systemctl(&["disable", "--now", DECODER_UNIT])?;
+    }
+
+    for unit in [DECODER_UNIT, COLLECT_UNIT] {
+        let unit_path = PathBuf::from(SYSTEMD_UNIT_PATH_STR.to_owned() + unit);
+        if unit_path.exists() {
+            remove_file(&unit_path).map_err(|err| {
+                format!("Could not remove {}. Error: {}", unit_path.display(), err)
+            })?;
+        }
+    }
+    systemctl(&["daemon-reload"])?;
+
+    println!("Removed systemd units.");
+
+    Ok(())
+}
+
+fn write_unit(name: &str, contents: &str) -> Result<(), String> {
+    let unit_path = PathBuf::from(SYSTEMD_UNIT_PATH_STR.to_owned() + name);
+    fs::write(&unit_path, contents)
+        .map_err(|err| format!("Could not write {}. Error: {}", unit_path.display(), err))
+}
+
+/// whether the decoder runs as cspi-decoder.service (or systemd is about to restart it), in which case systemd has to
+/// restart it
+pub fn decoder_unit_active() -> bool {
+    Command::new("systemctl")
+        .args(["is-active", DECODER_UNIT])
+        // e.g. "System has not been booted with systemd"
+        .stderr(Stdio::null())
+        .output()
+        .is_ok_and(|output| {
+            matches!(
+                String::from_utf8_lossy(&output.stdout).trim(),
+                "active" | "activating" | "deactivating" | "reloading"
+            )
+        })
+}
+
+pub fn restart_decoder_unit() -> Result<(), String> {
+    systemctl(&["restart", DECODER_UNIT])?;
+    println!("Restarted {}.", DECODER_UNIT);
+
+    Ok(())
+}
+
+fn is_installed(unit: &str) -> bool {
+    PathBuf::from(SYSTEMD_UNIT_PATH_STR.to_owned() + unit).exists()
+}
+
+/// instance names end up in unit names and the path of their configuration, so only characters valid in a unit name
+/// (except the escape character) are allowed
+fn validate_instance_name(name: &str) -> Result<(), String> {
+    let valid = |character: char| character.is_ascii_alphanumeric() || ":-_.".contains(character);
+    if name.is_empty() || name.starts_with('.') || !name.chars().all(valid) {
+        return Err(format!(
+            "Invalid instance name '{}': use letters, digits and ':', '-', '_' or '.' (not at the start)",
+            name
+        ));
+    }
+    // unit names are limited to 255 characters
+    if name.len() > 255 - "cspi-collect@.service".len() {
+        return Err(format!("Invalid instance name '{}': too long", name));
+    }
+    Ok(())
+}
+
+/// runs systemctl, an unsuccessful exit is an error
+pub fn systemctl(args: &[&str]) -> Result<(), String> {
+    let status = Command::new("systemctl")
+        .args(args)
+        .status()
+        .map_err(|err| format!("systemctl {} error: {}", args.join(" "), err))?;
+    if !status.success() {
+        return Err(format!("systemctl {} failed ({})", args.join(" "), status));
+    }
+
+    Ok(())
+}
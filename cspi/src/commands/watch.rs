@@ -0,0 +1,131 @@
+use std::fs;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::commands::{
+    apply::{apply, is_applied},
+    restore::restore,
+    running::{is_running, CollectionParameters},
+    start::start,
+    stop::stop,
+    NEXMON_DECODER_HEARTBEAT_STR,
+};
+
+/// how often the decoder heartbeat is checked
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// upper bound for the delay between two recovery attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+/// Starts CSI collection and keeps it alive. If no CSI has been decoded for longer than `timeout`,
+/// the firmware is assumed to have crashed and is restored, re-patched and restarted with the same parameters.
+pub fn watch(
+    parameters: &CollectionParameters,
+    timeout: &u64,
+    max_retries: &u32,
+    backoff: &u64,
+) -> Result<(), String> {
+    let timeout = Duration::from_secs(*timeout);
+    let backoff = Duration::from_secs(*backoff);
+
+    log_event(&format!(
+        "Watching CSI collection on channel {} (timeout: {}s, max. retries: {})",
+        parameters.channel,
+        timeout.as_secs(),
+        max_retries
+    ));
+
+    // (re)start CSI collection so we know which parameters are in use
+    if is_running() {
+        stop()?;
+    }
+    start(parameters)?;
+
+    let mut last_recovery = SystemTime::now();
+    let mut failed_attempts = 0;
+
+    loop {
+        sleep(POLL_INTERVAL);
+
+        // CSI counts as arriving if the decoder has seen a frame since collection was (re)started
+        let last_frame = last_frame_time()
+            .filter(|time| *time > last_recovery)
+            .unwrap_or(last_recovery);
+        let silence = SystemTime::now()
+            .duration_since(last_frame)
+            .unwrap_or_default();
+
+        if silence < timeout {
+            if failed_attempts > 0 {
+                log_event("CSI is arriving again, recovery successful.");
+                failed_attempts = 0;
+            }
+            continue;
+        }
+
+        if failed_attempts >= *max_retries {
+            log_event(&format!(
+                "No CSI after {} recovery attempts, giving up.",
+                failed_attempts
+            ));
+            return Err(String::from(
+                "Firmware could not be recovered, maximum number of retries reached",
+            ));
+        }
+
+        // wait longer after every failed attempt
+        if failed_attempts > 0 {
+            // large --retries or --backoff must not overflow
+            let delay = 2u32
+                .checked_pow(failed_attempts - 1)
+                .and_then(|factor| backoff.min(MAX_BACKOFF).checked_mul(factor))
+                .map_or(MAX_BACKOFF, |delay| delay.min(MAX_BACKOFF));
+            log_event(&format!(
+                "Waiting {}s before next recovery attempt...",
+                delay.as_secs()
+            ));
+            sleep(delay);
+        }
+
+        failed_attempts += 1;
+        log_event(&format!(
+            "No CSI received for {}s, firmware has likely crashed. Recovery attempt {} of {}...",
+            silence.as_secs(),
+            failed_attempts,
+            max_retries
+        ));
+
+        match recover(parameters) {
+            Ok(()) => log_event("Firmware restored, re-patched and collection restarted."),
+            Err(err) => log_event(&format!("Recovery attempt failed. Error: {}", err)),
+        }
+        last_recovery = SystemTime::now();
+    }
+}
+
+/// restores the original firmware, re-applies the patch and restarts collection.
+/// An earlier attempt may have failed after restoring, so the firmware is only restored if it is patched.
+fn recover(parameters: &CollectionParameters) -> Result<(), String> {
+    if is_applied() {
+        restore()?;
+    }
+    apply()?;
+    start(parameters)
+}
+
+/// time at which the decoder last decoded a frame
+fn last_frame_time() -> Option<SystemTime> {
+    let seconds = fs::read_to_string(NEXMON_DECODER_HEARTBEAT_STR)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+fn log_event(message: &str) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    println!("[{}] {}", timestamp, message);
+}
@@ -0,0 +1,84 @@
+use crate::csi::FrameType;
+
+/// 802.11 (type, subtype) of every frame type that has a name
+const FRAME_TYPES: [(FrameType, u8, u8); 22] = [
+    (FrameType::AssociationRequest, 0, 0),
+    (FrameType::AssociationResponse, 0, 1),
+    (FrameType::ReassociationRequest, 0, 2),
+    (FrameType::ReassociationResponse, 0, 3),
+    (FrameType::ProbeRequest, 0, 4),
+    (FrameType::ProbeResponse, 0, 5),
+    (FrameType::Beacon, 0, 8),
+    (FrameType::Disassociation, 0, 10),
+    (FrameType::Authentication, 0, 11),
+    (FrameType::Deauthentication, 0, 12),
+    (FrameType::Action, 0, 13),
+    (FrameType::NdpAnnouncement, 1, 5),
+    (FrameType::BlockAckRequest, 1, 8),
+    (FrameType::BlockAck, 1, 9),
+    (FrameType::PsPoll, 1, 10),
+    (FrameType::Rts, 1, 11),
+    (FrameType::Cts, 1, 12),
+    (FrameType::Ack, 1, 13),
+    (FrameType::Data, 2, 0),
+    (FrameType::NullData, 2, 4),
+    (FrameType::QosData, 2, 8),
+    (FrameType::QosNull, 2, 12),
+];
+
+/// interprets the first byte of the frame control field (protocol version, type, subtype)
+pub fn frame_type(fctl: u8) -> FrameType {
+    let frame_type = (fctl >> 2) & 0x03;
+    let subtype = fctl >> 4;
+    FRAME_TYPES
+        .iter()
+        .find(|(_, known_type, known_subtype)| {
+            *known_type == frame_type && *known_subtype == subtype
+        })
+        .map_or(FrameType::Other, |(frame_type, _, _)| *frame_type)
+}
+
+/// first byte of the frame control field of a frame type, as used by the firmware filter
+pub fn first_byte(frame_type: FrameType) -> Option<u8> {
+    FRAME_TYPES
+        .iter()
+        .find(|(known, _, _)| *known == frame_type)
+        .map(|(_, frame_type, subtype)| (subtype << 4) | (frame_type << 2))
+}
+
+/// name of a frame type as used on the command line (e.g. qos-data)
+pub fn frame_type_name(frame_type: FrameType) -> String {
+    frame_type
+        .as_str_name()
+        .trim_start_matches("FRAME_TYPE_")
+        .to_lowercase()
+        .replace('_', "-")
+}
+
+/// parses a frame type name as used on the command line
+pub fn parse_frame_type(name: &str) -> Result<FrameType, String> {
+    let proto_name = format!(
+        "FRAME_TYPE_{}",
+        name.trim().to_uppercase().replace('-', "_")
+    );
+    match FrameType::from_str_name(&proto_name) {
+        Some(FrameType::Other) | None => Err(format!(
+            "Unknown frame type '{}', expected one of: {}",
+            name,
+            FRAME_TYPES
+                .iter()
+                .map(|(frame_type, _, _)| frame_type_name(*frame_type))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )),
+        Some(frame_type) => Ok(frame_type),
+    }
+}
+
+/// parses a comma separated list of frame type names, empty if the list is empty
+pub fn parse_frame_types(list: &str) -> Result<Vec<FrameType>, String> {
+    list.split(',')
+        .filter(|name| !name.trim().is_empty())
+        .map(parse_frame_type)
+        .collect()
+}
@@ -0,0 +1,91 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::{stderr, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use syslog::Facility;
+
+/// where log messages are written to
+pub enum LogTarget {
+    /// standard error, ends up in journald when running as a systemd service
+    Stderr,
+    File(PathBuf),
+    Syslog,
+}
+
+/// logger writing timestamped messages to standard error or a file
+struct Logger {
+    level: LevelFilter,
+    file: Option<Mutex<File>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format!(
+            "{} {:<5} {}\n",
+            humantime::format_rfc3339_seconds(SystemTime::now()),
+            record.level(),
+            record.args()
+        );
+        match &self.file {
+            Some(file) => {
+                if let Ok(mut file) = file.lock() {
+                    let _ = file.write_all(line.as_bytes());
+                }
+            }
+            None => {
+                let _ = stderr().write_all(line.as_bytes());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(Ok(mut file)) = self.file.as_ref().map(|file| file.lock()) {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// sets up the global logger, panics are logged as well so they don't go unnoticed
+pub fn init(level: LevelFilter, target: LogTarget) -> Result<(), String> {
+    match target {
+        LogTarget::Syslog => syslog::init_unix(Facility::LOG_DAEMON, level)
+            .map_err(|err| format!("Could not connect to syslog. Error: {}", err))?,
+        LogTarget::Stderr => set_logger(Logger { level, file: None })?,
+        LogTarget::File(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|err| {
+                    format!("Could not open log file {}. Error: {}", path.display(), err)
+                })?;
+            set_logger(Logger {
+                level,
+                file: Some(Mutex::new(file)),
+            })?
+        }
+    }
+
+    std::panic::set_hook(Box::new(|info| log::error!("Panic: {}", info)));
+
+    Ok(())
+}
+
+fn set_logger(logger: Logger) -> Result<(), String> {
+    let level = logger.level;
+    log::set_boxed_logger(Box::new(logger))
+        .map_err(|err| format!("Could not set up logging. Error: {}", err))?;
+    log::set_max_level(level);
+
+    Ok(())
+}
@@ -0,0 +1,258 @@
+use std::fmt;
+use std::fs;
+
+/// maximum number of source addresses the firmware can filter on
+const FIRMWARE_MAX_ADDRESSES: usize = 4;
+
+/// formats a MAC address as decoded into NexmonData.source_mac (e.g. 11:22:33:44:55:66)
+pub fn format_mac(mac: u64) -> String {
+    mac.to_be_bytes()[2..]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+/// a single maclist entry
+#[derive(Clone, Copy, PartialEq)]
+enum MacPattern {
+    /// complete address
+    Address(u64),
+    /// first `len` bytes of an address (e.g. an OUI), written as 11:22:33:*
+    Prefix { prefix: u64, len: u32 },
+}
+
+impl MacPattern {
+    fn parse(entry: &str) -> Result<MacPattern, String> {
+        let invalid = |reason: &str| {
+            format!(
+                "Invalid entry '{}' in maclist: {} (expected an address like 11:22:33:44:55:66 or a prefix like 11:22:33:*)",
+                entry, reason
+            )
+        };
+
+        let (address, wildcard) = match entry.strip_suffix('*') {
+            Some(prefix) => (prefix.trim_end_matches([':', '-']), true),
+            None => (entry, false),
+        };
+        if address.is_empty() {
+            return Err(invalid("a prefix needs at least one byte"));
+        }
+
+        let mut value = 0u64;
+        let mut len = 0;
+        for byte in address.split([':', '-']) {
+            if byte.is_empty() || byte.len() > 2 {
+                return Err(invalid("bytes must be one or two hex digits"));
+            }
+            let byte = u8::from_str_radix(byte, 16)
+                .map_err(|_| invalid(&format!("'{}' is not a hex byte", byte)))?;
+            value = (value << 8) | byte as u64;
+            len += 1;
+        }
+
+        match (wildcard, len) {
+            (false, 6) => Ok(MacPattern::Address(value)),
+            (false, _) => Err(invalid(&format!("{} bytes instead of 6", len))),
+            (true, 1..=5) => Ok(MacPattern::Prefix { prefix: value, len }),
+            (true, _) => Err(invalid("a prefix can have at most 5 bytes")),
+        }
+    }
+
+    fn matches(&self, mac: u64) -> bool {
+        match *self {
+            MacPattern::Address(address) => mac == address,
+            MacPattern::Prefix { prefix, len } => mac >> (8 * (6 - len)) == prefix,
+        }
+    }
+}
+
+impl fmt::Display for MacPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MacPattern::Address(address) => write!(f, "{}", format_mac(address)),
+            MacPattern::Prefix { prefix, len } => {
+                let bytes = prefix.to_be_bytes();
+                for byte in &bytes[(8 - len as usize)..] {
+                    write!(f, "{:02x}:", byte)?;
+                }
+                write!(f, "*")
+            }
+        }
+    }
+}
+
+/// source addresses to evaluate CSI from, empty if CSI is evaluated from all sources
+#[derive(Clone, Default)]
+pub struct MacFilter {
+    patterns: Vec<MacPattern>,
+}
+
+impl MacFilter {
+    /// parses a comma separated list of addresses, prefixes (11:22:33:*) and files of those (@path)
+    pub fn parse(list: &str) -> Result<MacFilter, String> {
+        let mut filter = MacFilter::default();
+        for entry in list.split(',').map(str::trim) {
+            match entry.strip_prefix('@') {
+                Some(path) => filter.add_file(path)?,
+                None if entry.is_empty() => {}
+                None => filter.add(MacPattern::parse(&entry.to_lowercase())?),
+            }
+        }
+        Ok(filter)
+    }
+
+    /// adds the entries of a file (separated by commas, whitespace or newlines, # starts a comment)
+    fn add_file(&mut self, path: &str) -> Result<(), String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("Could not read MAC file {}. Error: {}", path, err))?;
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            for entry in line.split(|c: char| c == ',' || c.is_whitespace()) {
+                if !entry.is_empty() {
+                    self.add(
+                        MacPattern::parse(&entry.to_lowercase())
+                            .map_err(|err| format!("{} (in {})", err, path))?,
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, pattern: MacPattern) {
+        if !self.patterns.contains(&pattern) {
+            self.patterns.push(pattern);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn matches(&self, mac: u64) -> bool {
+        self.is_empty() || self.patterns.iter().any(|pattern| pattern.matches(mac))
+    }
+
+    /// maclist to pass to mcp, None if the firmware can't express the filter (prefixes or too many addresses)
+    pub fn firmware_maclist(&self) -> Option<String> {
+        let all_addresses = self
+            .patterns
+            .iter()
+            .all(|pattern| matches!(pattern, MacPattern::Address(_)));
+        match all_addresses && self.patterns.len() <= FIRMWARE_MAX_ADDRESSES {
+            true => Some(self.to_string()),
+            false => None,
+        }
+    }
+
+    /// describes the filter and where it is applied
+    pub fn describe(&self) -> String {
+        match (self.is_empty(), self.firmware_maclist()) {
+            (true, _) => String::from("all sources"),
+            (false, Some(_)) => format!("{} (filtered by the firmware)", self),
+            (false, None) => format!("{} (filtered by the decoder)", self),
+        }
+    }
+}
+
+impl fmt::Display for MacFilter {
+    /// comma separated list of the entries, as accepted by parse
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let entries = self
+            .patterns
+            .iter()
+            .map(MacPattern::to_string)
+            .collect::<Vec<String>>();
+        write!(f, "{}", entries.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_addresses() {
+        assert_eq!(format_mac(0x112233445566), "11:22:33:44:55:66");
+        assert_eq!(format_mac(0x0a), "00:00:00:00:00:0a");
+    }
+
+    #[test]
+    fn parses_addresses_and_prefixes() {
+        assert!(MacPattern::parse("11:22:33:44:55:66") == Ok(MacPattern::Address(0x112233445566)));
+        assert!(MacPattern::parse("1-2-3-4-5-6") == Ok(MacPattern::Address(0x010203040506)));
+        assert!(
+            MacPattern::parse("11:22:33:*")
+                == Ok(MacPattern::Prefix {
+                    prefix: 0x112233,
+                    len: 3
+                })
+        );
+        assert!(
+            MacPattern::parse("11:22:33*")
+                == Ok(MacPattern::Prefix {
+                    prefix: 0x112233,
+                    len: 3
+                })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        for entry in [
+            "11:22:33:44:55",
+            "11:22:33:44:55:66:77",
+            "11:22:33:44:55:6g",
+            "11:22:333:44:55:66",
+            "11::33:44:55:66",
+            "*",
+            "11:22:33:44:55:66:*",
+        ] {
+            assert!(MacPattern::parse(entry).is_err(), "{} was accepted", entry);
+        }
+    }
+
+    #[test]
+    fn prefixes_match_addresses_starting_with_them() {
+        let filter = MacFilter::parse("11:22:33:*").unwrap();
+        assert!(filter.matches(0x112233445566));
+        assert!(!filter.matches(0x112234445566));
+        assert!(MacFilter::parse("").unwrap().matches(0x112233445566));
+    }
+
+    #[test]
+    fn parses_maclists() {
+        let filter = MacFilter::parse(" 11:22:33:44:55:66 ,AA:BB:CC:*,,11:22:33:44:55:66").unwrap();
+        assert_eq!(filter.to_string(), "11:22:33:44:55:66,aa:bb:cc:*");
+        assert_eq!(filter.firmware_maclist(), None);
+        assert!(filter.matches(0xaabbcc000001));
+
+        let addresses = MacFilter::parse("11:22:33:44:55:66,11:22:33:44:55:67").unwrap();
+        assert_eq!(
+            addresses.firmware_maclist().as_deref(),
+            Some("11:22:33:44:55:66,11:22:33:44:55:67")
+        );
+        let too_many =
+            MacFilter::parse("1:1:1:1:1:1,2:2:2:2:2:2,3:3:3:3:3:3,4:4:4:4:4:4,5:5:5:5:5:5")
+                .unwrap();
+        assert_eq!(too_many.firmware_maclist(), None);
+    }
+
+    #[test]
+    fn reads_maclist_files() {
+        let path = std::env::temp_dir().join(format!("cspi-maclist-{}.txt", std::process::id()));
+        fs::write(
+            &path,
+            "# lab devices\n11:22:33:44:55:66 aa:bb:cc:* # access points\n\n01:02:03:04:05:06,\n",
+        )
+        .unwrap();
+        let filter = MacFilter::parse(&format!("@{},00:00:00:00:00:01", path.display()));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            filter.unwrap().to_string(),
+            "11:22:33:44:55:66,aa:bb:cc:*,01:02:03:04:05:06,00:00:00:00:00:01"
+        );
+        assert!(MacFilter::parse("@/nonexistent/maclist").is_err());
+    }
+}
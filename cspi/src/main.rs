@@ -1,15 +1,53 @@
-use clap::{Parser, Subcommand};
-use commands::collect::collect;
-use commands::decode::decode;
+use capture::Compression;
+use channel::Channel;
+use clap::{Args, Parser, Subcommand};
+use commands::collect::{collect, collect_triggered};
+use commands::detect::{
+    detect_breathing, detect_motion, parse_output_target, BreathingOptions, MotionOptions,
+    OutputTarget, MOTION_BASELINE_STR,
+};
 use commands::install::install;
+use commands::monitor::{monitor, MonitorOptions};
+use commands::plot::{plot, PlotKind, PlotOptions};
+use commands::record::{record, RecordOptions};
 use commands::restore::restore;
-use commands::running::running;
+use commands::resume::{disable_resume_on_boot, enable_resume_on_boot, resume};
+use commands::running::{running, CollectionParameters};
+use commands::spectrogram::{spectrogram, SpectrogramOptions};
 use commands::start::start;
 use commands::stop::stop;
-use commands::{apply::apply, decode::launch_decoder};
+use commands::sweep::sweep;
+use commands::systemd::{systemd_collect, systemd_install, systemd_uninstall};
+use commands::watch::watch;
+use commands::{
+    apply::apply,
+    decode::{decode_foreground, launch_decoder, restart_decoder, stop_decoder, DecoderOptions},
+};
+use frame::parse_frame_types;
+use log::LevelFilter;
+use mac::MacFilter;
+use processing::doppler::{DopplerInput, StftSettings};
+use processing::filter::{parse_filter_stage, FilterStage};
+use session::{default_directory, SessionMetadata};
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
+use trigger::{fire_trigger, parse_trigger, Trigger, TriggerOptions};
 
+mod capture;
+mod channel;
 mod commands;
+mod frame;
+mod logging;
+mod mac;
+mod metrics;
+mod mimo;
+mod mqtt;
+mod processing;
+mod sequence;
+mod session;
+mod subscribers;
+mod trigger;
 
 pub mod csi {
     include!(concat!(env!("OUT_DIR"), "/csi.rs"));
@@ -22,6 +60,148 @@ struct Cli {
     command: Commands,
 }
 
+/// parameters shared by all commands that start CSI collection
+#[derive(Args)]
+struct CollectionArgs {
+    /// wifi channel to collect CSI on, optionally with bandwidth and sideband (e.g. 36, 36/80 or 161/40u)
+    #[arg(short, long, default_value_t = String::from("36"))]
+    channel: String,
+    /// bandwidth to use if the channel does not specify one: 20, 40 or 80
+    #[arg(short, long, default_value_t = 80)]
+    bandwidth: u32,
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+impl CollectionArgs {
+    fn parameters(&self) -> Result<CollectionParameters, String> {
+        self.filter
+            .parameters(Channel::parse(&self.channel, self.bandwidth)?)
+    }
+}
+
+/// parameters shared by all commands that start CSI collection, except for the channel
+#[derive(Args)]
+struct FilterArgs {
+    /// comma separated list of source mac addresses (11:22:33:44:55:66), prefixes (11:22:33:*) or files of those (@path) to evaluate packets from
+    #[arg(short, long, default_value_t = String::from(""))]
+    maclist: String,
+    /// comma separated list of frame types to evaluate packets of (e.g. data,qos-data) [default: all]
+    #[arg(short, long, default_value_t = String::from(""), hide_default_value = true)]
+    frame_types: String,
+    /// bitmask of the cores (receive antennas) to collect CSI on, e.g. 0xf on chips with 4 cores
+    #[arg(short = 'C', long, default_value = "0x1", value_parser = parse_mask)]
+    cores: u8,
+    /// bitmask of the spatial streams to collect CSI of, e.g. 0xf on chips with 4 spatial streams
+    #[arg(short = 'N', long, default_value = "0x1", value_parser = parse_mask)]
+    streams: u8,
+}
+
+impl FilterArgs {
+    fn parameters(&self, channel: Channel) -> Result<CollectionParameters, String> {
+        Ok(CollectionParameters {
+            channel,
+            maclist: MacFilter::parse(&self.maclist)?,
+            frame_types: parse_frame_types(&self.frame_types)?,
+            cores: self.cores,
+            streams: self.streams,
+        })
+    }
+}
+
+/// description of a capture, stored in its session manifest
+#[derive(Args)]
+struct SessionArgs {
+    /// label to store in the session manifest as key=value (e.g. room=lab2), can be given several times
+    #[arg(long = "label", value_parser = parse_label)]
+    labels: Vec<(String, String)>,
+    /// note to store in the session manifest (e.g. "person walking")
+    #[arg(long)]
+    note: Option<String>,
+}
+
+impl SessionArgs {
+    fn metadata(&self) -> SessionMetadata {
+        SessionMetadata {
+            labels: self.labels.iter().cloned().collect(),
+            note: self.note.clone(),
+        }
+    }
+}
+
+/// trigger-based collection, only writing CSI around events
+#[derive(Args)]
+struct TriggerArgs {
+    /// collects until interrupted and only writes the CSI around every trigger: signal (SIGUSR1), socket (`cspi trigger`), rssi-jump:<dB> or variance:<threshold> (can be repeated)
+    #[arg(long, value_parser = parse_trigger, conflicts_with = "nexmon")]
+    trigger: Vec<Trigger>,
+    /// CSI before a trigger that is written
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    pre_trigger: Duration,
+    /// CSI after a trigger that is written
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    post_trigger: Duration,
+    /// comma separated list of sources that can fire rssi-jump and variance triggers [default: all]
+    #[arg(long, default_value_t = String::from(""), hide_default_value = true)]
+    trigger_sources: String,
+}
+
+impl TriggerArgs {
+    /// None unless a trigger was given
+    fn options(&self) -> Result<Option<TriggerOptions>, String> {
+        if self.trigger.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(TriggerOptions {
+            triggers: self.trigger.clone(),
+            sources: MacFilter::parse(&self.trigger_sources)?,
+            pre_trigger: self.pre_trigger,
+            post_trigger: self.post_trigger,
+        }))
+    }
+}
+
+/// parses a session label given as key=value
+fn parse_label(label: &str) -> Result<(String, String), String> {
+    match label.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_owned(), value.trim().to_owned()))
+        }
+        _ => Err(format!("'{}' is not of the form key=value", label)),
+    }
+}
+
+/// parses a size in bytes, optionally with a binary unit (e.g. 500M or 20G)
+fn parse_size(size: &str) -> Result<u64, String> {
+    let (number, factor) = match size.trim().to_uppercase().chars().last() {
+        Some('K') => (&size.trim()[..size.trim().len() - 1], 1 << 10),
+        Some('M') => (&size.trim()[..size.trim().len() - 1], 1 << 20),
+        Some('G') => (&size.trim()[..size.trim().len() - 1], 1 << 30),
+        Some('T') => (&size.trim()[..size.trim().len() - 1], 1 << 40),
+        _ => (size.trim(), 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(factor))
+        .ok_or(format!("'{}' is not a size (e.g. 500M or 20G)", size))
+}
+
+/// parses a core or spatial stream bitmask given in hex (0xf) or decimal (15)
+fn parse_mask(mask: &str) -> Result<u8, String> {
+    let value = match mask.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => mask.parse(),
+    }
+    .map_err(|_| format!("'{}' is not a bitmask", mask))?;
+    match value {
+        0x1..=0xf => Ok(value),
+        _ => Err(String::from(
+            "the mask must select between 1 and 4 of the bits 0x1 to 0x8",
+        )),
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// downloads and installs precompiled nexmon binary
@@ -36,45 +216,292 @@ enum Commands {
     Restore {},
     /// collects CSI into pcap file according to specified parameters
     Collect {
-        /// wifi channel to collect CSI on
-        #[arg(short, long, default_value_t = 36)]
-        channel: u32,
-        /// bandwidth to use: 20, 40 or 80
-        #[arg(short, long, default_value_t = 80)]
-        bandwidth: u32,
-        /// comma separated list of source mac addresses to evaluate packets from
-        #[arg(short, long, default_value_t = String::from(""))]
-        maclist: String,
+        #[command(flatten)]
+        collection: CollectionArgs,
         /// number of packets to collect
         #[arg(short, long, default_value_t = 1000)]
         packets: u32,
-        /// path of output file [default: ./output.pcap]
+        /// session directory to write the capture and its manifest to [default: ./cspi-<start time>]
         #[arg(short, long)]
         output: Option<PathBuf>,
         /// changes output format to original nexmon format
         #[arg(short, long, action)]
         nexmon: bool,
+        /// compresses the capture while writing it (not available with --nexmon)
+        #[arg(long, value_enum, conflicts_with = "nexmon")]
+        compress: Option<Compression>,
+        /// scales the CSI to the RSSI, so amplitudes are comparable across frames despite the automatic gain control
+        #[arg(long, conflicts_with = "nexmon")]
+        normalize: bool,
+        #[command(flatten)]
+        trigger: TriggerArgs,
+        #[command(flatten)]
+        session: SessionArgs,
     },
     /// starts CSI collection according to specified parameters (CSI will be available in nexmon format on UDP port 5500 and in protobuf format port 4400)
     Start {
-        /// wifi channel to collect CSI on
-        #[arg(short, long, default_value_t = 36)]
-        channel: u32,
-        /// bandwidth to use: 20, 40 or 80
-        #[arg(short, long, default_value_t = 80)]
-        bandwidth: u32,
-        /// comma separated list of source mac addresses to evaluate packets from
-        #[arg(short, long, default_value_t = String::from(""))]
-        maclist: String,
+        #[command(flatten)]
+        collection: CollectionArgs,
     },
     /// stops CSI collection
     Stop {},
     /// tells you whether CSI collection is currently running
     Running {},
+    /// restarts CSI collection with the parameters it was running with before a reboot
+    Resume {
+        /// installs a systemd unit that resumes collection at every boot
+        #[arg(long, conflicts_with = "disable")]
+        enable: bool,
+        /// removes the systemd unit installed with --enable
+        #[arg(long)]
+        disable: bool,
+    },
     /// starts the decoder and outputs protobuf messages on port 4400
-    Decode {},
+    Decode {
+        /// runs the decoder in the foreground instead of launching it in the background (used by the systemd unit)
+        #[arg(short, long)]
+        foreground: bool,
+        /// stops the running decoder
+        #[arg(long, conflicts_with_all = ["foreground", "restart"])]
+        stop: bool,
+        /// stops the running decoder and launches it again
+        #[arg(long, conflicts_with = "foreground")]
+        restart: bool,
+        /// minimum level of log messages: off, error, warn, info, debug or trace
+        #[arg(long, default_value_t = LevelFilter::Info)]
+        log_level: LevelFilter,
+        /// file to log to [default: standard error in the foreground, /home/pi/.cspi/nexmon-decoder.log otherwise]
+        #[arg(long, conflicts_with = "syslog")]
+        log_file: Option<PathBuf>,
+        /// logs to syslog instead of a file
+        #[arg(long)]
+        syslog: bool,
+        /// seconds between throughput summaries in the log
+        #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+        summary_interval: u64,
+        /// serves Prometheus metrics on http://<address>/metrics (e.g. 0.0.0.0:9400)
+        #[arg(long)]
+        metrics: Option<SocketAddr>,
+        /// fills in the number of frames lost since the previous frame of the transmitter (gap field)
+        #[arg(long)]
+        gaps: bool,
+        /// adds the amplitude, phase, subcarrier index and 802.11ac subcarrier type (data, pilot, guard, DC) of every CSI element
+        #[arg(long)]
+        derived: bool,
+        /// adds the sanitized phase of every CSI element: unwrapped, without linear trend and constant offset
+        #[arg(long)]
+        sanitize_phase: bool,
+        /// scales the CSI to the RSSI, so amplitudes are comparable across frames despite the automatic gain control
+        #[arg(long)]
+        normalize: bool,
+        /// filters the amplitude of every subcarrier over time per transmitter, sending the result as filtered_amplitude to subscribers asking for it: hampel[:<window>[:<threshold>]], moving-average:<window>, moving-median:<window>, savitzky-golay:<window>:<order>, interpolate (lost frames) or resample:<Hz> (can be repeated, applied in order)
+        #[arg(long, value_parser = parse_filter_stage)]
+        filter: Vec<FilterStage>,
+    },
     /// test
     Testdecode {},
+    /// collects CSI on several channels in turn, into one file per channel and cycle
+    Sweep {
+        /// comma separated list of channels to collect on (e.g. 36/80,44/80,149/80)
+        #[arg(short, long)]
+        channels: String,
+        /// bandwidth to use for channels that do not specify one: 20, 40 or 80
+        #[arg(short, long, default_value_t = 80)]
+        bandwidth: u32,
+        /// time to spend on every channel (e.g. 10s or 2m)
+        #[arg(short, long, default_value = "10s", value_parser = humantime::parse_duration)]
+        dwell: Duration,
+        /// number of times to go through all channels
+        #[arg(long, default_value_t = 1)]
+        cycles: u32,
+        /// session directory to write the captures and their manifest to [default: ./sweep-<start time>]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        filter: FilterArgs,
+        #[command(flatten)]
+        session: SessionArgs,
+    },
+    /// records CSI continuously until interrupted, in segments rotated by time or size
+    Record {
+        #[command(flatten)]
+        collection: CollectionArgs,
+        /// session directory to write the segments and their manifest to [default: ./record-<start time>]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// starts a new segment after this time (e.g. 1h or 30m)
+        #[arg(long, default_value = "1h", value_parser = humantime::parse_duration)]
+        rotate_every: Duration,
+        /// also starts a new segment once the current one reaches this size (e.g. 500M)
+        #[arg(long, value_parser = parse_size)]
+        rotate_size: Option<u64>,
+        /// compresses closed segments (while the next one is recorded)
+        #[arg(long, value_enum)]
+        compress: Option<Compression>,
+        /// deletes the oldest segments once all closed segments take up more than this (e.g. 20G)
+        #[arg(long, value_parser = parse_size)]
+        max_disk: Option<u64>,
+        #[command(flatten)]
+        session: SessionArgs,
+    },
+    /// starts CSI collection and automatically recovers from firmware crashes (restore, apply, start)
+    Watch {
+        #[command(flatten)]
+        collection: CollectionArgs,
+        /// seconds without any CSI after which the firmware is considered crashed (choose according to the expected traffic)
+        #[arg(short, long, default_value_t = 60)]
+        timeout: u64,
+        /// number of consecutive recovery attempts before giving up
+        #[arg(short, long, default_value_t = 5)]
+        retries: u32,
+        /// seconds to wait before the second recovery attempt, doubled for every further attempt
+        #[arg(long, default_value_t = 30)]
+        backoff: u64,
+    },
+    /// fires the socket trigger of a running `collect --trigger socket`
+    Trigger {
+        /// why the trigger was fired, noted in the session manifest
+        reason: Option<String>,
+    },
+    /// detects events from the decoded CSI (requires the decoder and running collection)
+    Detect {
+        #[command(subcommand)]
+        command: DetectCommands,
+    },
+    /// shows the frame rate, RSSI and loss of every transmitter and plots its amplitude and phase in the terminal
+    Monitor {
+        /// read CSI in nexmon format from port 5500 instead of subscribing to the decoder (requires the decoder to be stopped)
+        #[arg(long)]
+        raw: bool,
+        /// transmitters to show (comma separated MAC addresses, @file for a list) [default: all]
+        #[arg(short, long, default_value = "")]
+        maclist: String,
+    },
+    /// plots a capture (optionally zstd or xz compressed) to a PNG or SVG file
+    Plot {
+        /// capture to plot
+        capture: PathBuf,
+        /// what to plot: heatmap, amplitude or phase of the first transmitter, core and spatial stream, or rssi or rate of every transmitter
+        #[arg(short, long, value_enum, default_value = "heatmap")]
+        kind: PlotKind,
+        /// .png or .svg file to write [default: <capture>-<kind>.png next to the capture]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// transmitters to plot (comma separated MAC addresses, @file for a list) [default: all]
+        #[arg(short, long, default_value = "")]
+        maclist: String,
+        /// width of the plot in pixels
+        #[arg(long, default_value_t = 1280)]
+        width: u32,
+        /// height of the plot in pixels
+        #[arg(long, default_value_t = 720)]
+        height: u32,
+        /// TrueType font to draw text with [default: DejaVu Sans or another common system font]
+        #[arg(long)]
+        font: Option<PathBuf>,
+    },
+    /// computes Doppler spectrograms of a transmitter from a capture or the decoded CSI
+    Spectrogram {
+        /// capture (optionally zstd or xz compressed) to compute the spectrogram of [default: CSI from the decoder, until interrupted]
+        #[arg(short, long)]
+        input: Option<PathBuf>,
+        /// transmitter to use, e.g. 11:22:33:44:55:66 (only the first core and spatial stream seen are used) [default: the first transmitter seen]
+        #[arg(short, long, default_value = "")]
+        maclist: String,
+        /// what to transform: the amplitude, or the CSI of --core multiplied by the conjugate CSI of --reference-core
+        #[arg(long, value_enum, default_value = "amplitude")]
+        source: DopplerInput,
+        /// rate in Hz the CSI is resampled to (at most the frame rate of the transmitter and 1000), the spectrogram covers half of it
+        #[arg(long, default_value_t = 100.0)]
+        rate: f64,
+        /// length of the window every column is computed over
+        #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+        window: Duration,
+        /// time between columns
+        #[arg(long, default_value = "100ms", value_parser = humantime::parse_duration)]
+        step: Duration,
+        /// number of adjacent subcarriers whose spectra are averaged
+        #[arg(long, default_value_t = 16)]
+        group: usize,
+        /// core whose CSI is multiplied with the conjugate CSI of the reference core
+        #[arg(long, default_value_t = 1)]
+        core: u32,
+        /// core whose conjugate CSI the CSI of --core is multiplied with
+        #[arg(long, default_value_t = 0)]
+        reference_core: u32,
+        /// .npy (with its axes in a .json file next to it) or .png file to save the whole spectrogram to
+        #[arg(long)]
+        save: Option<PathBuf>,
+        /// where to stream columns: stdout, udp://<host>:<port> or mqtt://<host>[:<port>]/<topic> (can be repeated) [default: stdout unless --save is given]
+        #[arg(long, value_parser = parse_output_target)]
+        output: Vec<OutputTarget>,
+    },
+    /// manages systemd units for the decoder and CSI collection
+    Systemd {
+        #[command(subcommand)]
+        command: SystemdCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum SystemdCommands {
+    /// installs cspi-decoder.service and cspi-collect@.service, then enables and starts the decoder
+    Install {},
+    /// configures, enables and starts a cspi-collect@<name> instance
+    Collect {
+        /// name of the instance
+        name: String,
+        #[command(flatten)]
+        collection: CollectionArgs,
+    },
+    /// stops, disables and removes all cspi units
+    Uninstall {},
+}
+
+#[derive(Subcommand)]
+enum DetectCommands {
+    /// emits motion and idle events with their confidence, based on the variance of the CSI amplitude
+    Motion {
+        /// comma separated list of transmitters to use: addresses, prefixes (11:22:33:*) or files of those (@path) [default: all]
+        #[arg(short, long, default_value_t = String::from(""), hide_default_value = true)]
+        maclist: String,
+        /// sliding window the amplitude variance is computed over
+        #[arg(long, default_value = "2s", value_parser = humantime::parse_duration)]
+        window: Duration,
+        /// how often motion is evaluated
+        #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+        interval: Duration,
+        /// standard deviations above the empty-room baseline from which on there is motion
+        #[arg(long, default_value_t = 3.0)]
+        threshold: f64,
+        /// file the empty-room baseline is saved to and loaded from
+        #[arg(long, default_value = MOTION_BASELINE_STR)]
+        baseline: PathBuf,
+        /// learns the empty-room baseline for this long (e.g. 60s) instead of detecting
+        #[arg(long, value_parser = humantime::parse_duration)]
+        calibrate: Option<Duration>,
+        /// where to send events: stdout, udp://<host>:<port> or mqtt://<host>[:<port>]/<topic> (can be repeated) [default: stdout]
+        #[arg(long, value_parser = parse_output_target)]
+        output: Vec<OutputTarget>,
+    },
+    /// estimates the breathing rate (breaths per minute) of a person between a transmitter and the Pi
+    Breathing {
+        /// transmitter to use, e.g. 11:22:33:44:55:66 (only its first core and spatial stream seen are used)
+        #[arg(short, long)]
+        maclist: String,
+        /// rolling window the rate is estimated over (at least a few breaths)
+        #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+        window: Duration,
+        /// how often the rate is estimated
+        #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+        interval: Duration,
+        /// number of subcarriers most sensitive to breathing the estimate is based on
+        #[arg(long, default_value_t = 10)]
+        subcarriers: usize,
+        /// where to send estimates: stdout, udp://<host>:<port> or mqtt://<host>[:<port>]/<topic> (can be repeated) [default: stdout]
+        #[arg(long, value_parser = parse_output_target)]
+        output: Vec<OutputTarget>,
+    },
 }
 
 fn main() -> Result<(), String> {
@@ -90,38 +517,247 @@ fn main() -> Result<(), String> {
         Commands::Restore {} => restore()
             .map_err(|err| format!("Could not restore original firmware. Error: {}", err))?,
         Commands::Collect {
-            channel,
-            bandwidth,
-            maclist,
+            collection,
             packets,
             output,
             nexmon,
-        } => collect(
-            channel,
-            bandwidth,
-            maclist,
-            packets,
-            output.clone().unwrap_or(PathBuf::from("output.pcap")),
-            *nexmon,
-        )
-        .map_err(|err| format!("Could not collect the requested packets. Error: {}", err))?,
-        Commands::Start {
-            channel,
-            bandwidth,
-            maclist,
-        } => start(channel, bandwidth, maclist)
+            compress,
+            normalize,
+            trigger,
+            session,
+        } => match trigger.options()? {
+            Some(trigger_options) => collect_triggered(
+                &collection.parameters()?,
+                &trigger_options,
+                output.clone().unwrap_or(default_directory("cspi")),
+                *compress,
+                *normalize,
+                &session.metadata(),
+            )
+            .map_err(|err| format!("Triggered collection stopped. Error: {}", err))?,
+            None => collect(
+                &collection.parameters()?,
+                packets,
+                output.clone().unwrap_or(default_directory("cspi")),
+                *nexmon,
+                *compress,
+                *normalize,
+                &session.metadata(),
+            )
+            .map_err(|err| format!("Could not collect the requested packets. Error: {}", err))?,
+        },
+        Commands::Start { collection } => start(&collection.parameters()?)
             .map_err(|err| format!("Could not start CSI collection. Error: {}", err))?,
         Commands::Stop {} => {
             stop().map_err(|err| format!("Could not stop CSI collection. Error: {}", err))?
         }
         Commands::Running {} => running(),
-        Commands::Decode {} => launch_decoder().map_err(|err| {
-            format!(
-                "Decoder not launched. The most likely cause is that it was already running. Error: {}",
-                err
+        Commands::Resume { enable, disable } => match (enable, disable) {
+            (true, _) => enable_resume_on_boot()
+                .map_err(|err| format!("Could not enable resuming on boot. Error: {}", err))?,
+            (_, true) => disable_resume_on_boot()
+                .map_err(|err| format!("Could not disable resuming on boot. Error: {}", err))?,
+            _ => resume()
+                .map_err(|err| format!("Could not resume CSI collection. Error: {}", err))?,
+        },
+        Commands::Decode {
+            foreground,
+            stop,
+            restart,
+            log_level,
+            log_file,
+            syslog,
+            summary_interval,
+            metrics,
+            gaps,
+            derived,
+            sanitize_phase,
+            normalize,
+            filter,
+        } => {
+            let options = DecoderOptions {
+                log_level: *log_level,
+                log_file: log_file.clone(),
+                syslog: *syslog,
+                summary_interval: Duration::from_secs(*summary_interval),
+                metrics_address: *metrics,
+                gaps: *gaps,
+                derived: *derived,
+                sanitize_phase: *sanitize_phase,
+                normalize: *normalize,
+                filter: filter.clone(),
+            };
+            if *stop {
+                stop_decoder().map_err(|err| format!("Could not stop decoder. Error: {}", err))?
+            } else if *restart {
+                restart_decoder(&options)
+                    .map_err(|err| format!("Could not restart decoder. Error: {}", err))?
+            } else if *foreground {
+                decode_foreground(&options)
+                    .map_err(|err| format!("Decoder stopped. Error: {}", err))?
+            } else {
+                launch_decoder(&options).map_err(|err| {
+                    format!(
+                        "Decoder not launched. The most likely cause is that it was already running. Error: {}",
+                        err
+                    )
+                })?
+            }
+        }
+        Commands::Testdecode {} => decode_foreground(&DecoderOptions::default())
+            .map_err(|err| format!("Decoder stopped. Error: {}", err))?,
+        Commands::Watch {
+            collection,
+            timeout,
+            retries,
+            backoff,
+        } => watch(&collection.parameters()?, timeout, retries, backoff)
+            .map_err(|err| format!("Watchdog stopped. Error: {}", err))?,
+        Commands::Monitor { raw, maclist } => monitor(&MonitorOptions {
+            raw: *raw,
+            sources: MacFilter::parse(maclist)?,
+        })
+        .map_err(|err| format!("Monitor stopped. Error: {}", err))?,
+        Commands::Plot {
+            capture,
+            kind,
+            output,
+            maclist,
+            width,
+            height,
+            font,
+        } => plot(&PlotOptions {
+            capture: capture.clone(),
+            kind: *kind,
+            output: output.clone(),
+            sources: MacFilter::parse(maclist)?,
+            width: *width,
+            height: *height,
+            font: font.clone(),
+        })
+        .map_err(|err| format!("Could not plot capture. Error: {}", err))?,
+        Commands::Spectrogram {
+            input,
+            maclist,
+            source,
+            rate,
+            window,
+            step,
+            group,
+            core,
+            reference_core,
+            save,
+            output,
+        } => {
+            let settings = StftSettings {
+                input: *source,
+                rate: *rate,
+                window: window.as_micros() as u64,
+                step: step.as_micros() as u64,
+                group: *group,
+                core: *core,
+                reference_core: *reference_core,
+            };
+            settings.validate()?;
+            spectrogram(SpectrogramOptions {
+                input: input.clone(),
+                source: MacFilter::parse(maclist)?,
+                settings,
+                save: save.clone(),
+                outputs: output.clone(),
+            })
+            .map_err(|err| format!("Spectrogram stopped. Error: {}", err))?
+        }
+        Commands::Detect { command } => match command {
+            DetectCommands::Motion {
+                maclist,
+                window,
+                interval,
+                threshold,
+                baseline,
+                calibrate,
+                output,
+            } => detect_motion(&MotionOptions {
+                sources: MacFilter::parse(maclist)?,
+                window: *window,
+                interval: *interval,
+                threshold: *threshold,
+                baseline: baseline.clone(),
+                calibrate: *calibrate,
+                outputs: output.clone(),
+            })
+            .map_err(|err| format!("Motion detection stopped. Error: {}", err))?,
+            DetectCommands::Breathing {
+                maclist,
+                window,
+                interval,
+                subcarriers,
+                output,
+            } => detect_breathing(&BreathingOptions {
+                source: MacFilter::parse(maclist)?,
+                window: *window,
+                interval: *interval,
+                subcarriers: *subcarriers,
+                outputs: output.clone(),
+            })
+            .map_err(|err| format!("Breathing rate estimation stopped. Error: {}", err))?,
+        },
+        Commands::Trigger { reason } => {
+            fire_trigger(reason).map_err(|err| format!("Could not fire trigger. Error: {}", err))?
+        }
+        Commands::Record {
+            collection,
+            output,
+            rotate_every,
+            rotate_size,
+            compress,
+            max_disk,
+            session,
+        } => record(
+            &collection.parameters()?,
+            &RecordOptions {
+                rotate_every: Some(*rotate_every),
+                rotate_size: *rotate_size,
+                compress: *compress,
+                max_disk: *max_disk,
+            },
+            output.clone().unwrap_or(default_directory("record")),
+            &session.metadata(),
+        )
+        .map_err(|err| format!("Recording stopped. Error: {}", err))?,
+        Commands::Sweep {
+            channels,
+            bandwidth,
+            dwell,
+            cycles,
+            output,
+            filter,
+            session,
+        } => {
+            let sweep_parameters = channels
+                .split(',')
+                .filter(|channel| !channel.trim().is_empty())
+                .map(|channel| filter.parameters(Channel::parse(channel, *bandwidth)?))
+                .collect::<Result<Vec<CollectionParameters>, String>>()?;
+            sweep(
+                &sweep_parameters,
+                dwell,
+                cycles,
+                output.clone().unwrap_or(default_directory("sweep")),
+                &session.metadata(),
             )
-        })?,
-        Commands::Testdecode {} => decode(),
+            .map_err(|err| format!("Sweep stopped. Error: {}", err))?
+        }
+        Commands::Systemd { command } => match command {
+            SystemdCommands::Install {} => systemd_install()
+                .map_err(|err| format!("Could not install systemd units. Error: {}", err))?,
+            SystemdCommands::Collect { name, collection } => {
+                systemd_collect(name, &collection.parameters()?)
+                    .map_err(|err| format!("Could not start collection service. Error: {}", err))?
+            }
+            SystemdCommands::Uninstall {} => systemd_uninstall()
+                .map_err(|err| format!("Could not remove systemd units. Error: {}", err))?,
+        },
     }
 
     Ok(())
@@ -0,0 +1,271 @@
+use crate::capture::collected_bytes;
+use crate::csi::NexmonData;
+use crate::mac::format_mac;
+use crate::sequence::SequenceEvent;
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tiny_http::{Header, Response, Server};
+
+/// upper bounds of the RSSI histogram buckets in dBm
+const RSSI_BUCKETS: [i32; 8] = [-90, -80, -70, -60, -50, -40, -30, -20];
+
+/// counters and gauges of the capture pipeline, exported in the Prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    frames_received: u64,
+    frames_decoded: u64,
+    send_errors: u64,
+    parse_errors: BTreeMap<&'static str, u64>,
+    frames_by_source: BTreeMap<u64, u64>,
+    frames_by_core_stream: BTreeMap<(u32, u32), u64>,
+    lost_frames_by_source: BTreeMap<u64, u64>,
+    duplicate_frames_by_source: BTreeMap<u64, u64>,
+    reordered_frames_by_source: BTreeMap<u64, u64>,
+    rssi_buckets: [u64; RSSI_BUCKETS.len()],
+    rssi_sum: i64,
+    rssi_count: u64,
+    subscribers: usize,
+    last_frame_timestamp: f64,
+}
+
+impl Metrics {
+    pub fn frame_received(&mut self) {
+        self.frames_received += 1;
+    }
+
+    pub fn parse_error(&mut self, reason: &'static str) {
+        *self.parse_errors.entry(reason).or_default() += 1;
+    }
+
+    pub fn send_error(&mut self) {
+        self.send_errors += 1;
+    }
+
+    pub fn set_subscribers(&mut self, subscribers: usize) {
+        self.subscribers = subscribers;
+    }
+
+    pub fn frame_decoded(&mut self, nexmon_data: &NexmonData, sequence_event: SequenceEvent) {
+        self.frames_decoded += 1;
+        *self
+            .frames_by_source
+            .entry(nexmon_data.source_mac)
+            .or_default() += 1;
+        *self
+            .frames_by_core_stream
+            .entry((nexmon_data.core, nexmon_data.spatial_stream))
+            .or_default() += 1;
+
+        let (sequence_metric, count) = match sequence_event {
+            SequenceEvent::InOrder { lost } => (&mut self.lost_frames_by_source, lost as u64),
+            SequenceEvent::Duplicate => (&mut self.duplicate_frames_by_source, 1),
+            SequenceEvent::Reordered => (&mut self.reordered_frames_by_source, 1),
+            SequenceEvent::First => (&mut self.lost_frames_by_source, 0),
+        };
+        *sequence_metric.entry(nexmon_data.source_mac).or_default() += count;
+
+        for (bucket, bound) in RSSI_BUCKETS.iter().enumerate() {
+            if nexmon_data.rssi <= *bound {
+                self.rssi_buckets[bucket] += 1;
+            }
+        }
+        self.rssi_sum += nexmon_data.rssi as i64;
+        self.rssi_count += 1;
+
+        self.last_frame_timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+    }
+
+    /// renders all metrics in the Prometheus text exposition format
+    fn render(&self) -> String {
+        let mut output = String::new();
+
+        write_metric(
+            &mut output,
+            "cspi_frames_received_total",
+            "counter",
+            "Frames received from nexmon on port 5500.",
+            [(String::new(), self.frames_received)],
+        );
+        write_metric(
+            &mut output,
+            "cspi_frames_decoded_total",
+            "counter",
+            "Frames successfully decoded to protobuf.",
+            [(String::new(), self.frames_decoded)],
+        );
+        write_metric(
+            &mut output,
+            "cspi_parse_errors_total",
+            "counter",
+            "Malformed frames by reason.",
+            self.parse_errors
+                .iter()
+                .map(|(reason, count)| (format!("reason=\"{}\"", reason), *count)),
+        );
+        write_metric(
+            &mut output,
+            "cspi_send_errors_total",
+            "counter",
+            "Decoded frames that could not be sent.",
+            [(String::new(), self.send_errors)],
+        );
+        write_metric(
+            &mut output,
+            "cspi_source_frames_total",
+            "counter",
+            "Decoded frames by source MAC address.",
+            self.frames_by_source
+                .iter()
+                .map(|(mac, count)| (format!("mac=\"{}\"", format_mac(*mac)), *count)),
+        );
+        write_metric(
+            &mut output,
+            "cspi_core_stream_frames_total",
+            "counter",
+            "Decoded frames by core and spatial stream.",
+            self.frames_by_core_stream
+                .iter()
+                .map(|((core, stream), count)| {
+                    (format!("core=\"{}\",stream=\"{}\"", core, stream), *count)
+                }),
+        );
+        write_metric(
+            &mut output,
+            "cspi_sequence_lost_frames_total",
+            "counter",
+            "Frames missing according to gaps in the sequence numbers, by source MAC address.",
+            self.lost_frames_by_source
+                .iter()
+                .map(|(mac, count)| (format!("mac=\"{}\"", format_mac(*mac)), *count)),
+        );
+        write_metric(
+            &mut output,
+            "cspi_sequence_duplicate_frames_total",
+            "counter",
+            "Frames received more than once, by source MAC address.",
+            self.duplicate_frames_by_source
+                .iter()
+                .map(|(mac, count)| (format!("mac=\"{}\"", format_mac(*mac)), *count)),
+        );
+        write_metric(
+            &mut output,
+            "cspi_sequence_reordered_frames_total",
+            "counter",
+            "Frames received out of order, by source MAC address.",
+            self.reordered_frames_by_source
+                .iter()
+                .map(|(mac, count)| (format!("mac=\"{}\"", format_mac(*mac)), *count)),
+        );
+        write_metric(
+            &mut output,
+            "cspi_subscribers",
+            "gauge",
+            "Applications currently subscribed to decoded CSI (besides port 4400).",
+            [(String::new(), self.subscribers as u64)],
+        );
+
+        let _ = writeln!(output, "# HELP cspi_rssi_dbm RSSI of decoded frames.");
+        let _ = writeln!(output, "# TYPE cspi_rssi_dbm histogram");
+        for (bucket, bound) in RSSI_BUCKETS.iter().enumerate() {
+            let _ = writeln!(
+                output,
+                "cspi_rssi_dbm_bucket{{le=\"{}\"}} {}",
+                bound, self.rssi_buckets[bucket]
+            );
+        }
+        let _ = writeln!(
+            output,
+            "cspi_rssi_dbm_bucket{{le=\"+Inf\"}} {}",
+            self.rssi_count
+        );
+        let _ = writeln!(output, "cspi_rssi_dbm_sum {}", self.rssi_sum);
+        let _ = writeln!(output, "cspi_rssi_dbm_count {}", self.rssi_count);
+
+        let _ = writeln!(
+            output,
+            "# HELP cspi_last_frame_timestamp_seconds Time the last frame was decoded at."
+        );
+        let _ = writeln!(output, "# TYPE cspi_last_frame_timestamp_seconds gauge");
+        let _ = writeln!(
+            output,
+            "cspi_last_frame_timestamp_seconds {}",
+            self.last_frame_timestamp
+        );
+
+        // written by cspi collect, which runs in its own process
+        let collect_bytes = collected_bytes();
+        write_metric(
+            &mut output,
+            "cspi_collect_bytes_written_total",
+            "counter",
+            "Bytes written to capture files by cspi collect.",
+            [(String::new(), collect_bytes)],
+        );
+
+        output
+    }
+}
+
+fn write_metric(
+    output: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (String, u64)>,
+) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        match labels.is_empty() {
+            true => {
+                let _ = writeln!(output, "{} {}", name, value);
+            }
+            false => {
+                let _ = writeln!(output, "{}{{{}}} {}", name, labels, value);
+            }
+        }
+    }
+}
+
+/// serves the metrics on http://<address>/metrics in a background thread
+pub fn serve(address: SocketAddr, metrics: Arc<Mutex<Metrics>>) -> Result<(), String> {
+    let server = Server::http(address).map_err(|err| {
+        format!(
+            "Could not start metrics endpoint on {}. Error: {}",
+            address, err
+        )
+    })?;
+    info!("Serving metrics on http://{}/metrics", address);
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = match request.url() {
+                "/metrics" => {
+                    let body = metrics
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .render();
+                    Response::from_string(body).with_header(
+                        Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+                            .expect("static header is valid"),
+                    )
+                }
+                _ => Response::from_string("Not found, metrics are at /metrics\n")
+                    .with_status_code(404),
+            };
+            if let Err(err) = request.respond(response) {
+                warn!("Could not answer metrics request: {}", err);
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use crate::csi::{MimoData, NexmonData};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// incomplete groups are sent anyway if no further frame of the transmission arrives within this time
+const GROUP_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// groups the frames measured on different cores and spatial streams for the same transmission into MIMO CSI
+pub struct MimoGrouper {
+    /// frames per transmission (cores × spatial streams)
+    chains: usize,
+    /// frames of the latest transmission of every transmitter and when its first frame arrived
+    pending: HashMap<u64, (Instant, Vec<NexmonData>)>,
+}
+
+impl MimoGrouper {
+    pub fn new() -> MimoGrouper {
+        MimoGrouper {
+            chains: 1,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// sets the number of frames per transmission (cores × spatial streams)
+    pub fn set_chains(&mut self, chains: usize) {
+        self.chains = chains.max(1);
+    }
+
+    /// adds a frame, returns the groups completed by it
+    pub fn add(&mut self, nexmon_data: NexmonData) -> Vec<MimoData> {
+        let mut completed = vec![];
+
+        // a new sequence number means the previous transmission won't get any more frames
+        if let Some((_, frames)) = self.pending.get(&nexmon_data.source_mac) {
+            if frames[0].seq_num != nexmon_data.seq_num {
+                completed.extend(self.flush(nexmon_data.source_mac));
+            }
+        }
+
+        let source_mac = nexmon_data.source_mac;
+        let (_, frames) = self
+            .pending
+            .entry(source_mac)
+            .or_insert_with(|| (Instant::now(), vec![]));
+        frames.push(nexmon_data);
+        if frames.len() >= self.chains {
+            completed.extend(self.flush(source_mac));
+        }
+
+        completed
+    }
+
+    /// returns the groups that have been waiting for missing frames for too long
+    pub fn expire(&mut self) -> Vec<MimoData> {
+        let expired = self
+            .pending
+            .iter()
+            .filter(|(_, (since, _))| since.elapsed() >= GROUP_TIMEOUT)
+            .map(|(source_mac, _)| *source_mac)
+            .collect::<Vec<u64>>();
+        expired
+            .into_iter()
+            .filter_map(|source_mac| self.flush(source_mac))
+            .collect()
+    }
+
+    fn flush(&mut self, source_mac: u64) -> Option<MimoData> {
+        let (_, mut chains) = self.pending.remove(&source_mac)?;
+        chains.sort_by_key(|chain| (chain.core, chain.spatial_stream));
+        Some(MimoData {
+            source_mac,
+            seq_num: chains[0].seq_num,
+            frame_type: chains[0].frame_type,
+            chains,
+        })
+    }
+}
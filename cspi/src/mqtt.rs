@@ -0,0 +1,129 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+/// seconds the broker waits for a message from us before it drops the connection
+const KEEP_ALIVE: u16 = 60;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// minimal MQTT 3.1.1 client that publishes with QoS 0 and reconnects when the connection is lost
+pub struct MqttPublisher {
+    address: String,
+    stream: Option<TcpStream>,
+    last_sent: Instant,
+}
+
+impl MqttPublisher {
+    /// connects to a broker at host:port
+    pub fn connect(address: &str) -> Result<MqttPublisher, String> {
+        let mut publisher = MqttPublisher {
+            address: address.to_owned(),
+            stream: None,
+            last_sent: Instant::now(),
+        };
+        publisher.reconnect()?;
+        Ok(publisher)
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        self.stream = None;
+        let mut stream = TcpStream::connect(&self.address).map_err(|err| {
+            format!(
+                "Could not connect to MQTT broker {}. Error: {}",
+                self.address, err
+            )
+        })?;
+        stream
+            .set_read_timeout(Some(CONNECT_TIMEOUT))
+            .map_err(|err| format!("Could not configure MQTT connection. Error: {}", err))?;
+
+        let client_id = format!("cspi-{}", std::process::id());
+        let mut connect = vec![0, 4];
+        connect.extend_from_slice(b"MQTT");
+        // protocol level 4 (3.1.1), clean session
+        connect.extend_from_slice(&[4, 0x02]);
+        connect.extend_from_slice(&KEEP_ALIVE.to_be_bytes());
+        connect.extend_from_slice(&(client_id.len() as u16).to_be_bytes());
+        connect.extend_from_slice(client_id.as_bytes());
+
+        let mut connack = [0; 4];
+        stream
+            .write_all(&packet(0x10, &connect))
+            .and_then(|_| stream.read_exact(&mut connack))
+            .map_err(|err| {
+                format!(
+                    "MQTT broker {} did not accept connection. Error: {}",
+                    self.address, err
+                )
+            })?;
+        if connack[0] != 0x20 || connack[3] != 0 {
+            return Err(format!(
+                "MQTT broker {} refused connection (return code {})",
+                self.address, connack[3]
+            ));
+        }
+
+        self.stream = Some(stream);
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// publishes a message with QoS 0, reconnecting first if the connection was lost
+    pub fn publish(&mut self, topic: &str, payload: &[u8]) -> Result<(), String> {
+        if self.stream.is_none() {
+            self.reconnect()?;
+        }
+        let mut publish = (topic.len() as u16).to_be_bytes().to_vec();
+        publish.extend_from_slice(topic.as_bytes());
+        publish.extend_from_slice(payload);
+
+        let result = match self.stream.as_mut() {
+            Some(stream) => stream.write_all(&packet(0x30, &publish)),
+            None => return Err(String::from("Not connected to MQTT broker")),
+        };
+        if let Err(err) = result {
+            // try again with the next message
+            self.stream = None;
+            return Err(format!(
+                "Could not publish to MQTT broker {}. Error: {}",
+                self.address, err
+            ));
+        }
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// keeps the connection alive if nothing was published for a while
+    pub fn ping(&mut self) {
+        if self.last_sent.elapsed() < Duration::from_secs(KEEP_ALIVE as u64 / 2) {
+            return;
+        }
+        if let Some(stream) = self.stream.as_mut() {
+            // the response is never read, there are only ever a few bytes of it
+            if stream.write_all(&[0xc0, 0]).is_err() {
+                self.stream = None;
+            }
+        }
+        self.last_sent = Instant::now();
+    }
+}
+
+/// MQTT control packet with the given type and flags
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    // remaining length, 7 bits per byte
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
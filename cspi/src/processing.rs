@@ -0,0 +1,8 @@
+pub mod breathing;
+pub mod doppler;
+pub mod filter;
+pub mod motion;
+pub mod normalization;
+pub mod phase;
+pub mod spectrum;
+pub mod subcarriers;
@@ -0,0 +1,155 @@
+use rustfft::FftPlanner;
+use std::collections::VecDeque;
+
+use crate::csi::NexmonData;
+use crate::processing::motion::link_name;
+use crate::processing::spectrum::{power_spectrum, resample_uniform};
+use crate::processing::subcarriers::{is_occupied, subcarrier_indices};
+
+/// rate in Hz the amplitude is resampled to, well above the breathing band
+const RATE: f64 = 10.0;
+/// minimum FFT length (zero padded), for a resolution of RATE / FFT_LENGTH Hz (0.15 breaths per minute)
+const FFT_LENGTH: usize = 4096;
+/// breathing rates from 6 to 30 breaths per minute
+const BAND: (f64, f64) = (0.1, 0.5);
+/// frequencies this close to the estimated rate count towards its confidence
+const PEAK_WIDTH: f64 = 0.03;
+/// share of the window that has to be covered by frames before estimating
+const MIN_COVERAGE: f64 = 0.8;
+
+/// breathing rate estimated over a window
+pub struct BreathingEstimate {
+    pub breaths_per_minute: f64,
+    /// share of the power in the breathing band that is at the estimated rate
+    pub confidence: f64,
+    /// subcarriers the estimate is based on
+    pub subcarriers: Vec<i32>,
+    pub link: String,
+}
+
+/// Estimates the breathing rate from the amplitude of a single link: every data and pilot subcarrier is resampled,
+/// the subcarriers with the largest share of their power in the breathing band are selected, and the strongest
+/// frequency in the band of their combined spectrum is the breathing rate.
+pub struct BreathingEstimator {
+    /// window length in microseconds
+    window: u64,
+    /// number of subcarriers the estimate is based on
+    selected: usize,
+    /// the first link seen, frames of other cores and spatial streams are ignored
+    link: Option<String>,
+    subcarriers: Vec<i32>,
+    frames: VecDeque<(u64, Vec<f32>)>,
+    planner: FftPlanner<f32>,
+}
+
+impl BreathingEstimator {
+    pub fn new(window_us: u64, selected: usize) -> BreathingEstimator {
+        BreathingEstimator {
+            window: window_us,
+            selected: selected.max(1),
+            link: None,
+            subcarriers: vec![],
+            frames: VecDeque::new(),
+            planner: FftPlanner::new(),
+        }
+    }
+
+    pub fn add(&mut self, nexmon_data: &NexmonData) {
+        let link = link_name(nexmon_data);
+        if self.link.get_or_insert_with(|| link.clone()) != &link {
+            return;
+        }
+
+        let count = nexmon_data.csi.len();
+        let (subcarriers, amplitude): (Vec<i32>, Vec<f32>) = nexmon_data
+            .csi
+            .iter()
+            .zip(subcarrier_indices(count))
+            .filter(|(_, index)| is_occupied(*index, count))
+            .map(|(csi, index)| (index, (csi.real as f32).hypot(csi.imaginary as f32)))
+            .unzip();
+        // start over when the bandwidth changes
+        if subcarriers != self.subcarriers {
+            self.frames.clear();
+            self.subcarriers = subcarriers;
+        }
+        self.frames.push_back((nexmon_data.timestamp, amplitude));
+    }
+
+    /// drops frames older than the window, returns None until the window is covered
+    pub fn estimate(&mut self, now_us: u64) -> Option<BreathingEstimate> {
+        while self
+            .frames
+            .front()
+            .is_some_and(|(timestamp, _)| now_us.saturating_sub(*timestamp) > self.window)
+        {
+            self.frames.pop_front();
+        }
+        let start = self.frames.front()?.0;
+        let span = self.frames.back()?.0.saturating_sub(start);
+        if (span as f64) < self.window as f64 * MIN_COVERAGE {
+            return None;
+        }
+
+        let count = (span as f64 / 1_000_000.0 * RATE) as usize;
+        // long windows need no zero padding
+        let fft_length = FFT_LENGTH.max(count.next_power_of_two());
+        let bin = |frequency: f64| frequency * fft_length as f64 / RATE;
+        let band = (bin(BAND.0).ceil() as usize)..=(bin(BAND.1).floor() as usize);
+
+        // spectrum of every subcarrier, normalized to its total power, and the share of it in the breathing band
+        let mut spectra: Vec<(f32, usize, Vec<f32>)> = (0..self.subcarriers.len())
+            .map(|subcarrier| {
+                let samples: Vec<(u64, f32)> = self
+                    .frames
+                    .iter()
+                    .map(|(timestamp, amplitude)| (*timestamp, amplitude[subcarrier]))
+                    .collect();
+                let values = resample_uniform(&samples, start, RATE, count);
+                let spectrum = power_spectrum(&values, fft_length, &mut self.planner);
+                let total: f32 = spectrum[1..].iter().sum();
+                let normalized: Vec<f32> = match total > 0.0 {
+                    true => spectrum.iter().map(|power| power / total).collect(),
+                    false => vec![0.0; spectrum.len()],
+                };
+                let share = normalized[band.clone()].iter().sum();
+                (share, subcarrier, normalized)
+            })
+            .collect();
+        spectra.sort_by(|a, b| b.0.total_cmp(&a.0));
+        spectra.truncate(self.selected);
+
+        let mut combined = vec![0.0; fft_length / 2 + 1];
+        for (_, _, spectrum) in spectra.iter() {
+            for (sum, power) in combined.iter_mut().zip(spectrum) {
+                *sum += power;
+            }
+        }
+        let (peak, _) = combined[band.clone()]
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+        let peak = peak + band.start();
+        let band_power: f32 = combined[band.clone()].iter().sum();
+        let peak_width = bin(PEAK_WIDTH).round() as usize;
+        let peak_power: f32 = combined[peak.saturating_sub(peak_width).max(*band.start())
+            ..=(peak + peak_width).min(*band.end())]
+            .iter()
+            .sum();
+
+        let mut subcarriers: Vec<i32> = spectra
+            .iter()
+            .map(|(_, subcarrier, _)| self.subcarriers[*subcarrier])
+            .collect();
+        subcarriers.sort();
+        Some(BreathingEstimate {
+            breaths_per_minute: peak as f64 * RATE / fft_length as f64 * 60.0,
+            confidence: match band_power > 0.0 {
+                true => (peak_power / band_power) as f64,
+                false => 0.0,
+            },
+            subcarriers,
+            link: self.link.clone().unwrap_or_default(),
+        })
+    }
+}
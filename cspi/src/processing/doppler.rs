@@ -0,0 +1,294 @@
+use clap::ValueEnum;
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use std::collections::VecDeque;
+
+use crate::csi::NexmonData;
+use crate::mac::format_mac;
+use crate::processing::motion::link_name;
+use crate::processing::spectrum::{complex_power_spectrum, resample_uniform};
+use crate::processing::subcarriers::{is_occupied, subcarrier_indices};
+
+/// power below this is clipped before converting to dB, so empty bins don't end up at -inf
+const MIN_POWER: f32 = 1e-12;
+/// highest rate the CSI can be resampled to
+const MAX_RATE: f64 = 1000.0;
+/// most samples a window can hold, so every subcarrier's transform fits in memory
+const MAX_WINDOW_SAMPLES: f64 = 16384.0;
+/// frames a window needs, fewer (e.g. during a gap in collection) skip the column
+const MIN_FRAMES: usize = 4;
+
+/// what the Doppler spectrogram is computed from
+#[derive(Clone, Copy, ValueEnum)]
+pub enum DopplerInput {
+    /// amplitude of a single core and spatial stream, shows how fast but not in which direction things move
+    Amplitude,
+    /// CSI of a core multiplied by the conjugate CSI of a reference core, which cancels the phase offsets of the receiver and shows the direction too
+    Conjugate,
+}
+
+/// settings of the short-time Fourier transform
+pub struct StftSettings {
+    pub input: DopplerInput,
+    /// rate in Hz the CSI is resampled to, the spectrogram covers frequencies up to half of it
+    pub rate: f64,
+    /// window length in microseconds
+    pub window: u64,
+    /// time between columns in microseconds
+    pub step: u64,
+    /// number of adjacent subcarriers whose spectra are averaged
+    pub group: usize,
+    /// core used for conjugate multiplication
+    pub core: u32,
+    /// core whose conjugate CSI the CSI of `core` is multiplied with
+    pub reference_core: u32,
+}
+
+impl StftSettings {
+    /// rejects settings the spectrogram can't be computed with
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.rate > 0.0 && self.rate <= MAX_RATE) {
+            return Err(format!(
+                "--rate has to be above 0 and at most {} Hz",
+                MAX_RATE
+            ));
+        }
+        // the spectrogram works in whole microseconds
+        if self.window == 0 || self.step == 0 {
+            return Err(String::from("--window and --step have to be at least 1us"));
+        }
+        let window_samples = self.window as f64 / 1_000_000.0 * self.rate;
+        if window_samples > MAX_WINDOW_SAMPLES {
+            return Err(format!(
+                "--window holds {:.0} samples at --rate {}, at most {} are supported",
+                window_samples, self.rate, MAX_WINDOW_SAMPLES
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// column of a spectrogram
+pub struct SpectrogramColumn {
+    /// end of the window in microseconds since the Unix epoch
+    pub time: u64,
+    /// power in dB of every frequency, for every subcarrier group
+    pub power: Vec<Vec<f32>>,
+}
+
+/// Doppler spectrogram of a single link, computed as CSI arrives: the CSI of every data and pilot subcarrier is resampled
+/// to a uniform rate, every window is transformed after removing its mean (the static paths) and the power spectra of
+/// the subcarriers in a group are averaged.
+pub struct DopplerSpectrogram {
+    settings: StftSettings,
+    /// the first link seen, frames of other links are ignored
+    link: Option<String>,
+    subcarriers: Vec<i32>,
+    samples: VecDeque<(u64, Vec<Complex<f32>>)>,
+    /// latest frame of the core and of the reference core, to be multiplied once both of a transmission arrived
+    pending: [Option<(u32, Vec<Complex<f32>>)>; 2],
+    /// end of the window of the next column
+    next_column: Option<u64>,
+    planner: FftPlanner<f32>,
+}
+
+impl DopplerSpectrogram {
+    pub fn new(settings: StftSettings) -> DopplerSpectrogram {
+        DopplerSpectrogram {
+            settings: StftSettings {
+                group: settings.group.max(1),
+                ..settings
+            },
+            link: None,
+            subcarriers: vec![],
+            samples: VecDeque::new(),
+            pending: [None, None],
+            next_column: None,
+            planner: FftPlanner::new(),
+        }
+    }
+
+    pub fn add(&mut self, nexmon_data: &NexmonData) {
+        let count = nexmon_data.csi.len();
+        let (subcarriers, csi): (Vec<i32>, Vec<Complex<f32>>) = nexmon_data
+            .csi
+            .iter()
+            .zip(subcarrier_indices(count))
+            .filter(|(_, index)| is_occupied(*index, count))
+            .map(|(csi, index)| (index, Complex::new(csi.real as f32, csi.imaginary as f32)))
+            .unzip();
+
+        let sample = match self.settings.input {
+            DopplerInput::Amplitude => {
+                let link = link_name(nexmon_data);
+                if self.link.get_or_insert_with(|| link.clone()) != &link {
+                    return;
+                }
+                csi.iter()
+                    .map(|csi| Complex::new(csi.norm(), 0.0))
+                    .collect()
+            }
+            DopplerInput::Conjugate => {
+                let slot = match nexmon_data.core {
+                    core if core == self.settings.core => 0,
+                    core if core == self.settings.reference_core => 1,
+                    _ => return,
+                };
+                // named after the core, frames of the reference core have to come from the same transmitter and spatial stream
+                let link = format!(
+                    "{}/{}/{}",
+                    format_mac(nexmon_data.source_mac),
+                    self.settings.core,
+                    nexmon_data.spatial_stream
+                );
+                if self.link.get_or_insert_with(|| link.clone()) != &link {
+                    return;
+                }
+                self.pending[slot] = Some((nexmon_data.seq_num, csi));
+                match &self.pending {
+                    [Some((seq_num, csi)), Some((reference_seq_num, reference))]
+                        if seq_num == reference_seq_num && csi.len() == reference.len() =>
+                    {
+                        let product = csi
+                            .iter()
+                            .zip(reference)
+                            .map(|(csi, reference)| csi * reference.conj())
+                            .collect();
+                        self.pending = [None, None];
+                        product
+                    }
+                    _ => return,
+                }
+            }
+        };
+
+        // start over when the bandwidth changes
+        if subcarriers != self.subcarriers {
+            self.samples.clear();
+            self.next_column = None;
+            self.subcarriers = subcarriers;
+        }
+        self.next_column
+            .get_or_insert(nexmon_data.timestamp + self.settings.window);
+        self.samples.push_back((nexmon_data.timestamp, sample));
+    }
+
+    /// link the spectrogram is computed for
+    pub fn link(&self) -> Option<&str> {
+        self.link.as_deref()
+    }
+
+    fn fft_length(&self) -> usize {
+        self.window_samples().next_power_of_two()
+    }
+
+    fn window_samples(&self) -> usize {
+        ((self.settings.window as f64 / 1_000_000.0 * self.settings.rate).round() as usize).max(2)
+    }
+
+    /// frequency in Hz of every row of a column
+    pub fn frequencies(&self) -> Vec<f64> {
+        let fft_length = self.fft_length();
+        let resolution = self.settings.rate / fft_length as f64;
+        match self.settings.input {
+            DopplerInput::Amplitude => (0..=fft_length / 2)
+                .map(|bin| bin as f64 * resolution)
+                .collect(),
+            DopplerInput::Conjugate => (0..fft_length)
+                .map(|bin| (bin as f64 - (fft_length / 2) as f64) * resolution)
+                .collect(),
+        }
+    }
+
+    /// first and last subcarrier of every group
+    pub fn groups(&self) -> Vec<(i32, i32)> {
+        self.subcarriers
+            .chunks(self.settings.group)
+            .map(|group| (group[0], group[group.len() - 1]))
+            .collect()
+    }
+
+    /// every column whose window has passed since the last call
+    pub fn columns(&mut self) -> Vec<SpectrogramColumn> {
+        let mut columns = vec![];
+        let latest = match self.samples.back() {
+            Some((timestamp, _)) => *timestamp,
+            None => return columns,
+        };
+        while let Some(end) = self.next_column.filter(|end| *end <= latest) {
+            let start = end - self.settings.window;
+            // keep the frame before the window for interpolation
+            while self.samples.len() > 1 && self.samples[1].0 <= start {
+                self.samples.pop_front();
+            }
+            let frames = self
+                .samples
+                .iter()
+                .filter(|(timestamp, _)| (start..=end).contains(timestamp))
+                .count();
+            if frames >= MIN_FRAMES {
+                columns.push(SpectrogramColumn {
+                    time: end,
+                    power: self.column(start),
+                });
+            }
+            let step = self.settings.step.max(1);
+            // skip a pause in one go instead of step by step
+            let resume = self
+                .samples
+                .iter()
+                .map(|(timestamp, _)| *timestamp)
+                .find(|timestamp| *timestamp > end)
+                .map_or(end, |timestamp| {
+                    timestamp.saturating_sub(self.settings.window)
+                });
+            self.next_column = Some(end + step * (resume.saturating_sub(end) / step).max(1));
+        }
+        columns
+    }
+
+    fn column(&mut self, start: u64) -> Vec<Vec<f32>> {
+        let count = self.window_samples();
+        let fft_length = self.fft_length();
+        let spectra: Vec<Vec<f32>> = (0..self.subcarriers.len())
+            .map(|subcarrier| {
+                let part = |part: fn(&Complex<f32>) -> f32| {
+                    let samples: Vec<(u64, f32)> = self
+                        .samples
+                        .iter()
+                        .map(|(timestamp, csi)| (*timestamp, part(&csi[subcarrier])))
+                        .collect();
+                    resample_uniform(&samples, start, self.settings.rate, count)
+                };
+                let values: Vec<Complex<f32>> = part(|csi| csi.re)
+                    .into_iter()
+                    .zip(part(|csi| csi.im))
+                    .map(|(re, im)| Complex::new(re, im))
+                    .collect();
+                let spectrum = complex_power_spectrum(&values, fft_length, &mut self.planner);
+                match self.settings.input {
+                    // the spectrum of a real signal is symmetric, the Nyquist frequency is the first bin of the shifted one
+                    DopplerInput::Amplitude => {
+                        let mut one_sided = spectrum[fft_length / 2..].to_vec();
+                        one_sided.push(spectrum[0]);
+                        one_sided
+                    }
+                    DopplerInput::Conjugate => spectrum,
+                }
+            })
+            .collect();
+
+        spectra
+            .chunks(self.settings.group)
+            .map(|group| {
+                (0..group[0].len())
+                    .map(|bin| {
+                        let power = group.iter().map(|spectrum| spectrum[bin]).sum::<f32>()
+                            / group.len() as f32;
+                        10.0 * power.max(MIN_POWER).log10()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
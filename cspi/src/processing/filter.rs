@@ -0,0 +1,622 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use crate::csi::NexmonData;
+use crate::processing::normalization::normalize;
+use crate::sequence::sequence_number;
+
+/// 802.11 sequence numbers are 12 bit
+const SEQUENCE_MODULO: u32 = 4096;
+/// more lost frames than this are not interpolated, the filters start over instead
+const MAX_INTERPOLATED: u32 = 100;
+/// highest rate frames can be resampled to, every resampled frame is sent to every subscriber
+const MAX_RESAMPLE_RATE: f64 = 1000.0;
+/// resampling starts over after a pause of this many microseconds instead of filling it
+const MAX_RESAMPLE_GAP: u64 = 1_000_000;
+/// scales the median absolute deviation to the standard deviation of normally distributed values
+const MAD_SCALE: f32 = 1.4826;
+
+/// step of the filter pipeline, applied to the amplitude of every subcarrier over time
+#[derive(Clone, Copy)]
+pub enum FilterStage {
+    /// replaces values more than `threshold` scaled median absolute deviations from the median of the window by the median
+    Hampel {
+        window: usize,
+        threshold: f32,
+    },
+    MovingAverage {
+        window: usize,
+    },
+    MovingMedian {
+        window: usize,
+    },
+    /// least squares polynomial of the given order through the window, evaluated at the newest frame
+    SavitzkyGolay {
+        window: usize,
+        order: usize,
+    },
+    /// fills in the frames lost according to the sequence numbers by linear interpolation (always applied first)
+    Interpolate,
+    /// outputs frames at a fixed rate in Hz by linear interpolation in time (always applied last)
+    Resample {
+        rate: f64,
+    },
+}
+
+impl fmt::Display for FilterStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterStage::Hampel { window, threshold } => {
+                write!(f, "hampel:{}:{}", window, threshold)
+            }
+            FilterStage::MovingAverage { window } => write!(f, "moving-average:{}", window),
+            FilterStage::MovingMedian { window } => write!(f, "moving-median:{}", window),
+            FilterStage::SavitzkyGolay { window, order } => {
+                write!(f, "savitzky-golay:{}:{}", window, order)
+            }
+            FilterStage::Interpolate => write!(f, "interpolate"),
+            FilterStage::Resample { rate } => write!(f, "resample:{}", rate),
+        }
+    }
+}
+
+/// parses a filter stage, e.g. hampel:7:3, moving-average:5, moving-median:5, savitzky-golay:11:2, interpolate or resample:100
+pub fn parse_filter_stage(stage: &str) -> Result<FilterStage, String> {
+    let mut parts = stage.split(':');
+    let name = parts.next().unwrap_or_default().to_lowercase();
+    let arguments: Vec<&str> = parts.collect();
+
+    let invalid =
+        |expected: &str| format!("Invalid filter stage '{}' (expected {})", stage, expected);
+    let window = |argument: Option<&&str>, default: Option<usize>, expected: &str| {
+        match argument {
+            Some(argument) => argument.parse().ok(),
+            None => default,
+        }
+        .filter(|window| *window >= 2)
+        .ok_or(invalid(expected))
+    };
+
+    let parsed = match name.as_str() {
+        "hampel" if arguments.len() <= 2 => {
+            let expected = "hampel[:<window>[:<threshold>]], e.g. hampel:7:3";
+            FilterStage::Hampel {
+                window: window(arguments.first(), Some(7), expected)?,
+                threshold: match arguments.get(1) {
+                    Some(threshold) => threshold
+                        .parse()
+                        .ok()
+                        .filter(|threshold: &f32| *threshold > 0.0)
+                        .ok_or(invalid(expected))?,
+                    None => 3.0,
+                },
+            }
+        }
+        "moving-average" if arguments.len() == 1 => FilterStage::MovingAverage {
+            window: window(arguments.first(), None, "moving-average:<window>")?,
+        },
+        "moving-median" if arguments.len() == 1 => FilterStage::MovingMedian {
+            window: window(arguments.first(), None, "moving-median:<window>")?,
+        },
+        "savitzky-golay" if arguments.len() == 2 => {
+            let expected = "savitzky-golay:<window>:<order> with an order below the window, e.g. savitzky-golay:11:2";
+            let window = window(arguments.first(), None, expected)?;
+            let order = arguments[1]
+                .parse()
+                .ok()
+                .filter(|order| *order < window)
+                .ok_or(invalid(expected))?;
+            FilterStage::SavitzkyGolay { window, order }
+        }
+        "interpolate" if arguments.is_empty() => FilterStage::Interpolate,
+        "resample" if arguments.len() == 1 => FilterStage::Resample {
+            rate: arguments[0]
+                .parse()
+                .ok()
+                .filter(|rate: &f64| *rate > 0.0 && *rate <= MAX_RESAMPLE_RATE)
+                .ok_or(invalid("resample:<rate in Hz up to 1000>, e.g. resample:100"))?,
+        },
+        "hampel" | "moving-average" | "moving-median" | "savitzky-golay" | "interpolate"
+        | "resample" => return Err(invalid("a different number of arguments")),
+        _ => {
+            return Err(format!(
+                "Unknown filter stage '{}' (expected hampel, moving-average, moving-median, savitzky-golay, interpolate or resample)",
+                stage
+            ))
+        }
+    };
+    Ok(parsed)
+}
+
+/// window of a stage over the amplitudes of a source's recent frames
+struct StageState {
+    stage: FilterStage,
+    window: VecDeque<Vec<f32>>,
+    /// weights of the Savitzky–Golay filter, oldest frame first
+    weights: Vec<f32>,
+}
+
+impl StageState {
+    fn new(stage: FilterStage) -> StageState {
+        StageState {
+            stage,
+            window: VecDeque::new(),
+            weights: match stage {
+                FilterStage::SavitzkyGolay { window, order } => {
+                    savitzky_golay_weights(window, order)
+                }
+                _ => vec![],
+            },
+        }
+    }
+
+    fn apply(&mut self, amplitude: Vec<f32>) -> Vec<f32> {
+        let length = match self.stage {
+            FilterStage::Hampel { window, .. }
+            | FilterStage::MovingAverage { window }
+            | FilterStage::MovingMedian { window }
+            | FilterStage::SavitzkyGolay { window, .. } => window,
+            FilterStage::Interpolate | FilterStage::Resample { .. } => return amplitude,
+        };
+        if self.window.len() == length {
+            self.window.pop_front();
+        }
+        self.window.push_back(amplitude);
+
+        let newest = self.window.len() - 1;
+        (0..self.window[newest].len())
+            .map(|subcarrier| {
+                let values: Vec<f32> = self
+                    .window
+                    .iter()
+                    .map(|amplitude| amplitude[subcarrier])
+                    .collect();
+                match self.stage {
+                    FilterStage::Hampel { threshold, .. } => {
+                        let center = median(&values);
+                        let deviation = MAD_SCALE
+                            * median(
+                                &values
+                                    .iter()
+                                    .map(|value| (value - center).abs())
+                                    .collect::<Vec<f32>>(),
+                            );
+                        match (values[newest] - center).abs() > threshold * deviation {
+                            true => center,
+                            false => values[newest],
+                        }
+                    }
+                    FilterStage::MovingAverage { .. } => {
+                        values.iter().sum::<f32>() / values.len() as f32
+                    }
+                    FilterStage::MovingMedian { .. } => median(&values),
+                    // the polynomial can only be fitted once the window is full
+                    FilterStage::SavitzkyGolay { window, .. } if values.len() < window => {
+                        values[newest]
+                    }
+                    FilterStage::SavitzkyGolay { .. } => values
+                        .iter()
+                        .zip(self.weights.iter())
+                        .map(|(value, weight)| value * weight)
+                        .sum(),
+                    FilterStage::Interpolate | FilterStage::Resample { .. } => values[newest],
+                }
+            })
+            .collect()
+    }
+}
+
+/// filter state of a single source, core and spatial stream
+struct SourceState {
+    /// without the fragment number
+    last_seq_num: u32,
+    /// timestamp and amplitude of the last frame before filtering, interpolation starts from it
+    last_input: (u64, Vec<f32>),
+    stages: Vec<StageState>,
+    /// timestamp and amplitude of the last filtered frame and the time of the next resampled frame
+    last_output: Option<(u64, Vec<f32>)>,
+    next_sample: u64,
+}
+
+/// Filters the amplitude of every subcarrier over time, separately for every source, core and spatial stream.
+/// Filtered frames carry the result in filtered_amplitude, frames that were made up by interpolation or resampling
+/// are marked as interpolated and carry no CSI.
+pub struct Pipeline {
+    stages: Vec<FilterStage>,
+    interpolate: bool,
+    resample: Option<f64>,
+    /// filter the normalized amplitude
+    normalize: bool,
+    sources: HashMap<(u64, u32, u32), SourceState>,
+}
+
+impl Pipeline {
+    pub fn new(stages: &[FilterStage], normalize: bool) -> Pipeline {
+        let mut interpolate = false;
+        let mut resample = None;
+        for stage in stages {
+            match *stage {
+                FilterStage::Interpolate => interpolate = true,
+                FilterStage::Resample { rate } => resample = Some(rate),
+                _ => {}
+            }
+        }
+        Pipeline {
+            stages: stages.to_vec(),
+            interpolate,
+            resample,
+            normalize,
+            sources: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// filters a frame, returns the frames to send in its place (none while resampling is waiting for the next sample time)
+    pub fn process(&mut self, nexmon_data: &NexmonData) -> Vec<NexmonData> {
+        let mut input = nexmon_data.clone();
+        if self.normalize {
+            normalize(&mut input);
+        }
+        let amplitude: Vec<f32> = input
+            .csi
+            .iter()
+            .map(|csi| (csi.real as f32).hypot(csi.imaginary as f32))
+            .collect();
+
+        let key = (
+            nexmon_data.source_mac,
+            nexmon_data.core,
+            nexmon_data.spatial_stream,
+        );
+        let seq_num = sequence_number(nexmon_data.seq_num);
+        let step = self
+            .sources
+            .get(&key)
+            .map(|state| (seq_num + SEQUENCE_MODULO - state.last_seq_num) % SEQUENCE_MODULO);
+        // duplicates and frames arriving out of order would disturb the filters
+        if step.is_some_and(|step| step == 0 || step >= SEQUENCE_MODULO / 2) {
+            return vec![];
+        }
+        // start over after long losses and when the bandwidth changes
+        let restart = step.is_some_and(|step| step > MAX_INTERPOLATED + 1)
+            || self
+                .sources
+                .get(&key)
+                .is_some_and(|state| state.last_input.1.len() != amplitude.len());
+        if restart || step.is_none() {
+            self.sources.insert(
+                key,
+                SourceState {
+                    last_seq_num: seq_num,
+                    last_input: (nexmon_data.timestamp, amplitude.clone()),
+                    stages: self
+                        .stages
+                        .iter()
+                        .map(|stage| StageState::new(*stage))
+                        .collect(),
+                    last_output: None,
+                    next_sample: nexmon_data.timestamp,
+                },
+            );
+        }
+        let state = match self.sources.get_mut(&key) {
+            Some(state) => state,
+            None => return vec![],
+        };
+
+        // frames to run through the filters: interpolated ones for the lost frames, then the received one
+        let mut frames = vec![];
+        let lost = step.filter(|_| !restart).unwrap_or(1) - 1;
+        if self.interpolate && lost > 0 {
+            let (last_timestamp, last_amplitude) = &state.last_input;
+            for i in 1..=lost {
+                let fraction = i as f32 / (lost + 1) as f32;
+                let mut interpolated = NexmonData {
+                    csi: vec![],
+                    // in the sequence control format of received frames, fragment number 0
+                    seq_num: ((state.last_seq_num + i) % SEQUENCE_MODULO) << 4,
+                    timestamp: last_timestamp
+                        + ((nexmon_data.timestamp.saturating_sub(*last_timestamp)) as f64
+                            * fraction as f64) as u64,
+                    interpolated: true,
+                    ..nexmon_data.clone()
+                };
+                interpolated.gap = 0;
+                frames.push((interpolated, lerp(last_amplitude, &amplitude, fraction)));
+            }
+        }
+        frames.push((nexmon_data.clone(), amplitude.clone()));
+        state.last_seq_num = seq_num;
+        state.last_input = (nexmon_data.timestamp, amplitude);
+
+        let mut output = vec![];
+        for (mut frame, amplitude) in frames {
+            let filtered = state
+                .stages
+                .iter_mut()
+                .fold(amplitude, |amplitude, stage| stage.apply(amplitude));
+            match self.resample {
+                None => {
+                    frame.filtered_amplitude = filtered;
+                    output.push(frame);
+                }
+                Some(rate) => output.extend(resample(state, frame, filtered, rate)),
+            }
+        }
+        output
+    }
+}
+
+/// frames at the sample times up to a filtered frame, interpolated between it and the previous one
+fn resample(
+    state: &mut SourceState,
+    frame: NexmonData,
+    filtered: Vec<f32>,
+    rate: f64,
+) -> Vec<NexmonData> {
+    let period = (1_000_000.0 / rate).max(1.0) as u64;
+    let timestamp = frame.timestamp;
+    let mut output = vec![];
+
+    match &state.last_output {
+        Some((last_timestamp, _))
+            if timestamp.saturating_sub(*last_timestamp) > MAX_RESAMPLE_GAP =>
+        {
+            state.next_sample = timestamp;
+        }
+        _ => {}
+    }
+    while state.next_sample <= timestamp {
+        let amplitude = match &state.last_output {
+            Some((last_timestamp, last_filtered)) if *last_timestamp < timestamp => lerp(
+                last_filtered,
+                &filtered,
+                (state.next_sample.saturating_sub(*last_timestamp)) as f32
+                    / (timestamp - last_timestamp) as f32,
+            ),
+            _ => filtered.clone(),
+        };
+        output.push(NexmonData {
+            csi: vec![],
+            timestamp: state.next_sample,
+            filtered_amplitude: amplitude,
+            interpolated: true,
+            ..frame.clone()
+        });
+        state.next_sample += period;
+    }
+    state.last_output = Some((timestamp, filtered));
+    output
+}
+
+/// linear interpolation between two amplitude vectors
+fn lerp(from: &[f32], to: &[f32], fraction: f32) -> Vec<f32> {
+    from.iter()
+        .zip(to)
+        .map(|(from, to)| from + (to - from) * fraction)
+        .collect()
+}
+
+fn median(values: &[f32]) -> f32 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    match sorted.len() {
+        0 => 0.0,
+        len if len % 2 == 0 => (sorted[len / 2 - 1] + sorted[len / 2]) / 2.0,
+        len => sorted[len / 2],
+    }
+}
+
+/// weights that evaluate the least squares polynomial through `window` values at the newest one, oldest value first
+fn savitzky_golay_weights(window: usize, order: usize) -> Vec<f32> {
+    // positions relative to the newest value, which is at 0
+    let positions: Vec<f64> = (0..window)
+        .map(|i| i as f64 - (window - 1) as f64)
+        .collect();
+    let terms = order + 1;
+
+    // normal equations (AᵀA) c = Aᵀy, the weights are the first row of (AᵀA)⁻¹Aᵀ since the polynomial is evaluated at 0
+    let mut normal = vec![vec![0.0; terms]; terms];
+    for (row, normal_row) in normal.iter_mut().enumerate() {
+        for (column, value) in normal_row.iter_mut().enumerate() {
+            *value = positions
+                .iter()
+                .map(|position| position.powi((row + column) as i32))
+                .sum();
+        }
+    }
+    let inverse_row = match first_row_of_inverse(normal) {
+        Some(inverse_row) => inverse_row,
+        // cannot happen for order < window, fall back to passing the newest value through
+        None => {
+            let mut weights = vec![0.0; window];
+            weights[window - 1] = 1.0;
+            return weights;
+        }
+    };
+
+    positions
+        .iter()
+        .map(|position| {
+            inverse_row
+                .iter()
+                .enumerate()
+                .map(|(term, coefficient)| coefficient * position.powi(term as i32))
+                .sum::<f64>() as f32
+        })
+        .collect()
+}
+
+/// first row of the inverse of a symmetric matrix, by Gauss–Jordan elimination
+fn first_row_of_inverse(mut matrix: Vec<Vec<f64>>) -> Option<Vec<f64>> {
+    let size = matrix.len();
+    let mut inverse: Vec<Vec<f64>> = (0..size)
+        .map(|row| {
+            (0..size)
+                .map(|column| (row == column) as u8 as f64)
+                .collect()
+        })
+        .collect();
+
+    for column in 0..size {
+        let pivot = (column..size).max_by(|a, b| {
+            matrix[*a][column]
+                .abs()
+                .total_cmp(&matrix[*b][column].abs())
+        })?;
+        if matrix[pivot][column].abs() < f64::EPSILON {
+            return None;
+        }
+        matrix.swap(column, pivot);
+        inverse.swap(column, pivot);
+
+        let divisor = matrix[column][column];
+        for i in 0..size {
+            matrix[column][i] /= divisor;
+            inverse[column][i] /= divisor;
+        }
+        for row in 0..size {
+            if row != column {
+                let factor = matrix[row][column];
+                for i in 0..size {
+                    matrix[row][i] -= factor * matrix[column][i];
+                    inverse[row][i] -= factor * inverse[column][i];
+                }
+            }
+        }
+    }
+
+    // the matrix is symmetric, so its inverse is too and the first row equals the first column
+    Some(inverse.into_iter().map(|row| row[0]).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csi::Csi;
+
+    /// runs values of a single subcarrier through a stage
+    fn run(stage: &str, values: &[f32]) -> Vec<f32> {
+        let mut state = StageState::new(parse_filter_stage(stage).unwrap());
+        values
+            .iter()
+            .map(|value| state.apply(vec![*value])[0])
+            .collect()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() < 1e-4,
+                "{:?} instead of {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn parses_stages() {
+        for stage in [
+            "hampel:7:3",
+            "moving-average:5",
+            "moving-median:5",
+            "savitzky-golay:11:2",
+            "interpolate",
+            "resample:100",
+        ] {
+            assert_eq!(parse_filter_stage(stage).unwrap().to_string(), stage);
+        }
+        assert_eq!(
+            parse_filter_stage("Hampel").unwrap().to_string(),
+            "hampel:7:3"
+        );
+        for stage in [
+            "hampel:1",
+            "hampel:7:0",
+            "moving-average",
+            "moving-median:x",
+            "savitzky-golay:5:5",
+            "interpolate:2",
+            "resample:0",
+            "resample:NaN",
+            "resample:1e9",
+            "kalman",
+        ] {
+            assert!(parse_filter_stage(stage).is_err(), "{} was accepted", stage);
+        }
+    }
+
+    #[test]
+    fn hampel_replaces_outliers_by_the_median() {
+        assert_close(
+            &run("hampel:5:3", &[10.0, 10.0, 11.0, 10.0, 100.0]),
+            &[10.0, 10.0, 10.0, 10.0, 10.0],
+        );
+        assert_close(
+            &run("hampel:5:3", &[10.0, 12.0, 11.0, 10.0, 11.0, 13.0]),
+            &[10.0, 12.0, 11.0, 10.0, 11.0, 13.0],
+        );
+    }
+
+    #[test]
+    fn moving_median_and_average() {
+        assert_close(
+            &run("moving-median:3", &[1.0, 5.0, 2.0, 8.0, 3.0]),
+            &[1.0, 3.0, 2.0, 5.0, 3.0],
+        );
+        assert_close(
+            &run("moving-average:2", &[1.0, 5.0, 2.0, 8.0]),
+            &[1.0, 3.0, 3.5, 5.0],
+        );
+    }
+
+    #[test]
+    fn savitzky_golay_weights_match_known_coefficients() {
+        // evaluated at the newest of 5 values, the counterpart of the centered [-3, 12, 17, 12, -3] / 35
+        let expected: Vec<f32> = [3.0, -5.0, -3.0, 9.0, 31.0]
+            .iter()
+            .map(|weight| weight / 35.0)
+            .collect();
+        assert_close(&savitzky_golay_weights(5, 2), &expected);
+        assert_close(&savitzky_golay_weights(5, 1), &[-0.2, 0.0, 0.2, 0.4, 0.6]);
+        assert_close(&savitzky_golay_weights(3, 0), &[1.0 / 3.0; 3]);
+        // a polynomial up to the order passes unchanged
+        let quadratic: Vec<f32> = (0..8).map(|x| (x * x) as f32 - 3.0 * x as f32).collect();
+        assert_close(&run("savitzky-golay:5:2", &quadratic), &quadratic);
+    }
+
+    #[test]
+    fn interpolates_lost_frames() {
+        let mut pipeline = Pipeline::new(&[parse_filter_stage("interpolate").unwrap()], false);
+        let frame = |seq_num: u32, timestamp: u64, real: i32| NexmonData {
+            seq_num: seq_num << 4,
+            timestamp,
+            csi: vec![Csi { real, imaginary: 0 }],
+            ..NexmonData::default()
+        };
+        assert_eq!(pipeline.process(&frame(4095, 1000, 10)).len(), 1);
+        let output = pipeline.process(&frame(2, 4000, 40));
+        assert_eq!(
+            output
+                .iter()
+                .map(|frame| (frame.seq_num >> 4, frame.timestamp, frame.interpolated))
+                .collect::<Vec<(u32, u64, bool)>>(),
+            [(0, 2000, true), (1, 3000, true), (2, 4000, false)]
+        );
+        assert_close(
+            &output
+                .iter()
+                .map(|frame| frame.filtered_amplitude[0])
+                .collect::<Vec<f32>>(),
+            &[20.0, 30.0, 40.0],
+        );
+        // duplicates and late frames are dropped
+        assert!(pipeline.process(&frame(2, 4100, 40)).is_empty());
+        assert!(pipeline.process(&frame(1, 4200, 40)).is_empty());
+    }
+}
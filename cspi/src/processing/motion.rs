@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::csi::NexmonData;
+use crate::mac::format_mac;
+use crate::processing::subcarriers::{is_occupied, subcarrier_indices};
+
+/// frames a link needs in the window before its statistic is computed
+const MIN_FRAMES: usize = 10;
+/// smallest standard deviation of the baseline relative to its mean, so a very steady calibration does not make every change look like motion
+const MIN_RELATIVE_DEVIATION: f64 = 0.05;
+
+/// link between a transmitter and one core and spatial stream of the receiver, e.g. 11:22:33:44:55:66/0/0
+pub fn link_name(nexmon_data: &NexmonData) -> String {
+    format!(
+        "{}/{}/{}",
+        format_mac(nexmon_data.source_mac),
+        nexmon_data.core,
+        nexmon_data.spatial_stream
+    )
+}
+
+/// Motion statistic of every link over a sliding window: the variance of the amplitude of every data and pilot subcarrier
+/// relative to its squared mean, averaged over the subcarriers. It does not depend on the signal strength and rises when
+/// something moves between transmitter and receiver.
+pub struct MotionStatistic {
+    /// window length in microseconds
+    window: u64,
+    links: HashMap<String, VecDeque<(u64, Vec<f32>)>>,
+}
+
+impl MotionStatistic {
+    pub fn new(window_us: u64) -> MotionStatistic {
+        MotionStatistic {
+            window: window_us,
+            links: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, nexmon_data: &NexmonData) {
+        let count = nexmon_data.csi.len();
+        let amplitude: Vec<f32> = nexmon_data
+            .csi
+            .iter()
+            .zip(subcarrier_indices(count))
+            .filter(|(_, index)| is_occupied(*index, count))
+            .map(|(csi, _)| (csi.real as f32).hypot(csi.imaginary as f32))
+            .collect();
+
+        let frames = self.links.entry(link_name(nexmon_data)).or_default();
+        // start over when the bandwidth changes
+        if frames
+            .back()
+            .is_some_and(|(_, last)| last.len() != amplitude.len())
+        {
+            frames.clear();
+        }
+        frames.push_back((nexmon_data.timestamp, amplitude));
+    }
+
+    /// drops frames older than the window and computes the statistic of every link with enough frames in it
+    pub fn compute(&mut self, now_us: u64) -> BTreeMap<String, f64> {
+        let window = self.window;
+        self.links.retain(|_, frames| {
+            while frames
+                .front()
+                .is_some_and(|(timestamp, _)| now_us.saturating_sub(*timestamp) > window)
+            {
+                frames.pop_front();
+            }
+            !frames.is_empty()
+        });
+
+        self.links
+            .iter()
+            .filter(|(_, frames)| frames.len() >= MIN_FRAMES)
+            .map(|(link, frames)| {
+                let subcarriers = frames[0].1.len();
+                let statistic = (0..subcarriers)
+                    .map(|subcarrier| {
+                        let values = frames
+                            .iter()
+                            .map(|(_, amplitude)| amplitude[subcarrier] as f64);
+                        let mean = values.clone().sum::<f64>() / frames.len() as f64;
+                        let variance = values.map(|value| (value - mean).powi(2)).sum::<f64>()
+                            / frames.len() as f64;
+                        match mean > 0.0 {
+                            true => variance / mean.powi(2),
+                            false => 0.0,
+                        }
+                    })
+                    .sum::<f64>()
+                    / subcarriers.max(1) as f64;
+                (link.clone(), statistic)
+            })
+            .collect()
+    }
+}
+
+/// motion statistic of a link in the empty room
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct LinkBaseline {
+    pub mean: f64,
+    pub deviation: f64,
+    pub samples: u64,
+}
+
+/// motion statistic of every link in the empty room, learned during calibration
+#[derive(Serialize, Deserialize, Default)]
+pub struct Baseline {
+    pub window_seconds: f64,
+    pub links: BTreeMap<String, LinkBaseline>,
+}
+
+impl Baseline {
+    /// baseline from the statistics observed during calibration
+    pub fn learn(window_seconds: f64, samples: &BTreeMap<String, Vec<f64>>) -> Baseline {
+        Baseline {
+            window_seconds,
+            links: samples
+                .iter()
+                .filter(|(_, values)| !values.is_empty())
+                .map(|(link, values)| {
+                    let mean = values.iter().sum::<f64>() / values.len() as f64;
+                    let variance = values
+                        .iter()
+                        .map(|value| (value - mean).powi(2))
+                        .sum::<f64>()
+                        / values.len() as f64;
+                    (
+                        link.clone(),
+                        LinkBaseline {
+                            mean,
+                            deviation: variance.sqrt(),
+                            samples: values.len() as u64,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// how many standard deviations the statistic of a link is above its baseline, None if the link was not calibrated
+    pub fn score(&self, link: &str, statistic: f64) -> Option<f64> {
+        self.links.get(link).map(|baseline| {
+            let deviation = baseline
+                .deviation
+                .max(baseline.mean * MIN_RELATIVE_DEVIATION)
+                .max(f64::EPSILON);
+            (statistic - baseline.mean) / deviation
+        })
+    }
+}
+
+/// probability-like confidence that there is motion for a score, 0.5 at the threshold
+pub fn motion_probability(score: f64, threshold: f64) -> f64 {
+    1.0 / (1.0 + (threshold - score).exp())
+}
@@ -0,0 +1,40 @@
+use crate::csi::NexmonData;
+use crate::processing::subcarriers::{is_occupied, subcarrier_indices};
+
+/// RSSI (dBm) at which normalized CSI has REFERENCE_AMPLITUDE
+const REFERENCE_RSSI: f64 = -60.0;
+/// root mean square amplitude of the occupied subcarriers of normalized CSI received at REFERENCE_RSSI
+const REFERENCE_AMPLITUDE: f64 = 1000.0;
+
+/// Scales the CSI of a frame to its RSSI, undoing the automatic gain control of the chip.
+/// After scaling, the root mean square amplitude of the data and pilot subcarriers is REFERENCE_AMPLITUDE at REFERENCE_RSSI
+/// and changes by 20 dB per 20 dB of RSSI, so amplitudes are comparable across frames.
+pub fn normalize(nexmon_data: &mut NexmonData) {
+    if nexmon_data.normalized {
+        return;
+    }
+    let count = nexmon_data.csi.len();
+    let powers: Vec<f64> = nexmon_data
+        .csi
+        .iter()
+        .zip(subcarrier_indices(count))
+        .filter(|(_, index)| is_occupied(*index, count))
+        .map(|(csi, _)| (csi.real as f64).powi(2) + (csi.imaginary as f64).powi(2))
+        .collect();
+    if powers.is_empty() {
+        return;
+    }
+    let rms_amplitude = (powers.iter().sum::<f64>() / powers.len() as f64).sqrt();
+    // without any signal there is nothing to scale
+    if rms_amplitude == 0.0 {
+        return;
+    }
+
+    let scale = REFERENCE_AMPLITUDE * 10f64.powf((nexmon_data.rssi as f64 - REFERENCE_RSSI) / 20.0)
+        / rms_amplitude;
+    for csi in nexmon_data.csi.iter_mut() {
+        csi.real = (csi.real as f64 * scale).round() as i32;
+        csi.imaginary = (csi.imaginary as f64 * scale).round() as i32;
+    }
+    nexmon_data.normalized = true;
+}
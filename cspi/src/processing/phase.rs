@@ -0,0 +1,76 @@
+use std::f32::consts::PI;
+
+use crate::csi::NexmonData;
+use crate::processing::subcarriers::{is_occupied, subcarrier_indices};
+
+/// Fills in the sanitized phase of every CSI element: the phase is unwrapped across the occupied subcarriers,
+/// then the least squares line through it (sampling time and frequency offset plus constant phase offset) is removed.
+/// Null subcarriers get NaN, since their phase is meaningless.
+pub fn add_sanitized_phase(nexmon_data: &mut NexmonData) {
+    let count = nexmon_data.csi.len();
+    let occupied: Vec<(usize, f32)> = subcarrier_indices(count)
+        .enumerate()
+        .filter(|(_, index)| is_occupied(*index, count))
+        .map(|(position, index)| (position, index as f32))
+        .collect();
+
+    let phase = unwrap(
+        occupied
+            .iter()
+            .map(|(position, _)| {
+                let csi = &nexmon_data.csi[*position];
+                (csi.imaginary as f32).atan2(csi.real as f32)
+            })
+            .collect(),
+    );
+    let (slope, offset) = linear_fit(
+        &occupied
+            .iter()
+            .map(|(_, index)| *index)
+            .collect::<Vec<f32>>(),
+        &phase,
+    );
+
+    let mut sanitized_phase = vec![f32::NAN; count];
+    for ((position, index), phase) in occupied.iter().zip(phase) {
+        sanitized_phase[*position] = phase - (slope * index + offset);
+    }
+    nexmon_data.sanitized_phase = sanitized_phase;
+}
+
+/// removes jumps of more than π between neighbouring values
+fn unwrap(mut phase: Vec<f32>) -> Vec<f32> {
+    let mut correction = 0.0;
+    for i in 1..phase.len() {
+        let difference = phase[i] + correction - phase[i - 1];
+        if difference > PI {
+            correction -= 2.0 * PI * ((difference + PI) / (2.0 * PI)).floor();
+        } else if difference < -PI {
+            correction += 2.0 * PI * ((PI - difference) / (2.0 * PI)).floor();
+        }
+        phase[i] += correction;
+    }
+    phase
+}
+
+/// slope and intercept of the least squares line through the points
+fn linear_fit(x: &[f32], y: &[f32]) -> (f32, f32) {
+    if x.is_empty() {
+        return (0.0, 0.0);
+    }
+    let n = x.len() as f32;
+    let mean_x = x.iter().sum::<f32>() / n;
+    let mean_y = y.iter().sum::<f32>() / n;
+    let covariance: f32 = x
+        .iter()
+        .zip(y)
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f32 = x.iter().map(|x| (x - mean_x).powi(2)).sum();
+    let slope = if variance > 0.0 {
+        covariance / variance
+    } else {
+        0.0
+    };
+    (slope, mean_y - slope * mean_x)
+}
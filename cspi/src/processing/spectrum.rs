@@ -0,0 +1,80 @@
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use std::f32::consts::PI;
+
+/// values at `rate` Hz from `start` on, linearly interpolated between irregularly timed samples (microseconds, oldest first)
+pub fn resample_uniform(samples: &[(u64, f32)], start: u64, rate: f64, count: usize) -> Vec<f32> {
+    let period = 1_000_000.0 / rate;
+    let mut next = 0;
+    (0..count)
+        .map(|i| {
+            let time = start + (i as f64 * period) as u64;
+            while next < samples.len() && samples[next].0 < time {
+                next += 1;
+            }
+            match next {
+                0 => samples.first().map_or(0.0, |sample| sample.1),
+                next if next == samples.len() => samples[next - 1].1,
+                next => {
+                    let (before_time, before) = samples[next - 1];
+                    let (after_time, after) = samples[next];
+                    let fraction = match after_time - before_time {
+                        0 => 0.0,
+                        span => (time - before_time) as f32 / span as f32,
+                    };
+                    before + (after - before) * fraction
+                }
+            }
+        })
+        .collect()
+}
+
+/// Hann window of the given length
+pub fn hann(length: usize) -> Vec<f32> {
+    (0..length)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / length.max(2) as f32).cos())
+        .collect()
+}
+
+/// power of the positive frequencies (bin i at i * rate / fft_length) of the mean-free, Hann windowed values, zero padded to `fft_length`
+pub fn power_spectrum(
+    values: &[f32],
+    fft_length: usize,
+    planner: &mut FftPlanner<f32>,
+) -> Vec<f32> {
+    let mean = values.iter().sum::<f32>() / values.len().max(1) as f32;
+    let mut buffer: Vec<Complex<f32>> = values
+        .iter()
+        .zip(hann(values.len()))
+        .map(|(value, weight)| Complex::new((value - mean) * weight, 0.0))
+        .collect();
+    buffer.resize(fft_length.max(values.len()), Complex::new(0.0, 0.0));
+
+    planner.plan_fft_forward(buffer.len()).process(&mut buffer);
+    buffer[..buffer.len() / 2 + 1]
+        .iter()
+        .map(|bin| bin.norm_sqr())
+        .collect()
+}
+
+/// power of every frequency of the mean-free, Hann windowed complex values, zero padded to `fft_length`,
+/// from the most negative to the most positive (bin i at (i - fft_length / 2) * rate / fft_length)
+pub fn complex_power_spectrum(
+    values: &[Complex<f32>],
+    fft_length: usize,
+    planner: &mut FftPlanner<f32>,
+) -> Vec<f32> {
+    let mean = values.iter().sum::<Complex<f32>>() / values.len().max(1) as f32;
+    let mut buffer: Vec<Complex<f32>> = values
+        .iter()
+        .zip(hann(values.len()))
+        .map(|(value, weight)| (value - mean) * weight)
+        .collect();
+    buffer.resize(fft_length.max(values.len()), Complex::new(0.0, 0.0));
+
+    planner.plan_fft_forward(buffer.len()).process(&mut buffer);
+    // zero frequency to the middle
+    let half = buffer.len() / 2;
+    buffer.rotate_right(half);
+    buffer.iter().map(|bin| bin.norm_sqr()).collect()
+}
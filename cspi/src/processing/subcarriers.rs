@@ -0,0 +1,61 @@
+use crate::csi::{NexmonData, SubcarrierType};
+
+/// pilot subcarriers of 802.11ac (positive side, mirrored on the negative side) for 64, 128 and 256 subcarriers
+const PILOTS_20: [i32; 2] = [7, 21];
+const PILOTS_40: [i32; 3] = [11, 25, 53];
+const PILOTS_80: [i32; 4] = [11, 39, 75, 103];
+
+/// subcarrier index of every CSI element, from -N/2 to N/2-1
+pub fn subcarrier_indices(count: usize) -> impl Iterator<Item = i32> {
+    let half = (count / 2) as i32;
+    -half..(count as i32 - half)
+}
+
+/// 802.11ac type of a subcarrier in CSI of `count` subcarriers (20, 40 or 80 MHz)
+pub fn subcarrier_type(index: i32, count: usize) -> SubcarrierType {
+    // highest occupied subcarrier, DC subcarriers on either side of 0 and pilots
+    let (edge, dc, pilots): (i32, i32, &[i32]) = match count {
+        64 => (28, 0, &PILOTS_20),
+        128 => (58, 1, &PILOTS_40),
+        256 => (122, 1, &PILOTS_80),
+        // no layout for other sizes, so every subcarrier is treated as data
+        _ => return SubcarrierType::Data,
+    };
+
+    if index.abs() > edge {
+        SubcarrierType::Guard
+    } else if index.abs() <= dc {
+        SubcarrierType::Dc
+    } else if pilots.contains(&index.abs()) {
+        SubcarrierType::Pilot
+    } else {
+        SubcarrierType::Data
+    }
+}
+
+/// whether a subcarrier carries a signal (data or pilot) rather than being a null subcarrier
+pub fn is_occupied(index: i32, count: usize) -> bool {
+    matches!(
+        subcarrier_type(index, count),
+        SubcarrierType::Data | SubcarrierType::Pilot
+    )
+}
+
+/// fills in the amplitude and phase of every CSI element, along with its subcarrier index and type
+pub fn add_derived(nexmon_data: &mut NexmonData) {
+    let count = nexmon_data.csi.len();
+    nexmon_data.amplitude = nexmon_data
+        .csi
+        .iter()
+        .map(|csi| (csi.real as f32).hypot(csi.imaginary as f32))
+        .collect();
+    nexmon_data.phase = nexmon_data
+        .csi
+        .iter()
+        .map(|csi| (csi.imaginary as f32).atan2(csi.real as f32))
+        .collect();
+    nexmon_data.subcarrier = subcarrier_indices(count).collect();
+    nexmon_data.subcarrier_type = subcarrier_indices(count)
+        .map(|index| subcarrier_type(index, count) as i32)
+        .collect();
+}
@@ -0,0 +1,252 @@
+use crate::mac::format_mac;
+use std::collections::BTreeMap;
+
+/// 802.11 sequence numbers are 12 bit
+const SEQUENCE_MODULO: u32 = 4096;
+/// steps of at least half the sequence space are considered to go backwards
+const MAX_FORWARD_STEP: u32 = SEQUENCE_MODULO / 2;
+/// frames up to this far behind the newest frame arrived out of order, frames further behind mean the transmitter
+/// restarted or was gone for long, and counting starts over from them
+const REORDER_WINDOW: u32 = 64;
+
+/// how a frame relates to the previous frames of its transmitter
+#[derive(Clone, Copy, PartialEq)]
+pub enum SequenceEvent {
+    /// first frame seen from this transmitter
+    First,
+    /// next frame in order, after the given number of lost frames
+    InOrder { lost: u32 },
+    /// same sequence number, core and spatial stream as an earlier frame
+    Duplicate,
+    /// older than the newest frame seen, i.e. arrived out of order
+    Reordered,
+}
+
+/// sequence number of a frame, nexmon reports the whole 802.11 sequence control field with the fragment number in the
+/// lowest 4 bits
+pub fn sequence_number(seq_num: u32) -> u32 {
+    (seq_num >> 4) % SEQUENCE_MODULO
+}
+
+/// loss statistics of a single transmitter
+#[derive(Default, Clone)]
+pub struct SourceStatistics {
+    /// sequence numbers received, frames of several cores and spatial streams of a transmission count once like lost
+    /// ones do
+    pub received: u64,
+    pub lost: u64,
+    pub duplicates: u64,
+    pub reordered: u64,
+    last_seq_num: u32,
+    /// cores and spatial streams already seen with the last sequence number (bit core * 8 + stream)
+    last_chains: u64,
+    /// sequence numbers within the reorder window counted as lost (bit n - 1 for n behind the last sequence number)
+    missing: u64,
+}
+
+impl SourceStatistics {
+    /// share of frames lost, between 0 and 1
+    pub fn loss_rate(&self) -> f64 {
+        match self.received + self.lost {
+            0 => 0.0,
+            expected => self.lost as f64 / expected as f64,
+        }
+    }
+}
+
+/// tracks the sequence numbers of every transmitter to detect lost, duplicate and reordered frames
+#[derive(Default)]
+pub struct SequenceTracker {
+    sources: BTreeMap<u64, SourceStatistics>,
+}
+
+impl SequenceTracker {
+    pub fn track(
+        &mut self,
+        source_mac: u64,
+        seq_num: u32,
+        core: u32,
+        spatial_stream: u32,
+    ) -> SequenceEvent {
+        let seq_num = sequence_number(seq_num);
+        let chain = 1u64 << ((core % 8) * 8 + spatial_stream % 8);
+
+        let statistics = match self.sources.get_mut(&source_mac) {
+            Some(statistics) => statistics,
+            None => {
+                self.sources.insert(
+                    source_mac,
+                    SourceStatistics {
+                        received: 1,
+                        last_seq_num: seq_num,
+                        last_chains: chain,
+                        ..Default::default()
+                    },
+                );
+                return SequenceEvent::First;
+            }
+        };
+
+        let step = (seq_num + SEQUENCE_MODULO - statistics.last_seq_num) % SEQUENCE_MODULO;
+        if step == 0 {
+            // frames measured on other cores and spatial streams share the sequence number and were received already
+            if statistics.last_chains & chain != 0 {
+                statistics.duplicates += 1;
+                return SequenceEvent::Duplicate;
+            }
+            statistics.last_chains |= chain;
+            return SequenceEvent::InOrder { lost: 0 };
+        }
+        if step >= MAX_FORWARD_STEP && SEQUENCE_MODULO - step <= REORDER_WINDOW {
+            statistics.reordered += 1;
+            // only a frame that was counted as lost when the gap was detected turned up after all
+            let missing = 1u64 << (SEQUENCE_MODULO - step - 1);
+            if statistics.missing & missing != 0 {
+                statistics.missing &= !missing;
+                statistics.lost = statistics.lost.saturating_sub(1);
+                statistics.received += 1;
+            }
+            return SequenceEvent::Reordered;
+        }
+
+        let event = if step < MAX_FORWARD_STEP {
+            let lost = step - 1;
+            statistics.lost += lost as u64;
+            statistics.missing = statistics.missing.checked_shl(step).unwrap_or(0)
+                | 1u64.checked_shl(lost).unwrap_or(0).wrapping_sub(1);
+            SequenceEvent::InOrder { lost }
+        } else {
+            statistics.missing = 0;
+            SequenceEvent::InOrder { lost: 0 }
+        };
+        statistics.last_seq_num = seq_num;
+        statistics.last_chains = chain;
+        statistics.received += 1;
+
+        event
+    }
+
+    /// returns the statistics so far and counts from zero again, still detecting gaps to the frames seen so far
+    pub fn take(&mut self) -> SequenceTracker {
+        let taken = SequenceTracker {
+            sources: self.sources.clone(),
+        };
+        for statistics in self.sources.values_mut() {
+            statistics.received = 0;
+            statistics.lost = 0;
+            statistics.duplicates = 0;
+            statistics.reordered = 0;
+        }
+        taken
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = (&u64, &SourceStatistics)> {
+        self.sources.iter()
+    }
+
+    /// prints the loss statistics of every transmitter
+    pub fn print_summary(&self) {
+        if self.sources.is_empty() {
+            return;
+        }
+
+        println!("Loss summary by transmitter:");
+        for (source_mac, statistics) in self.sources() {
+            println!(
+                "  {}: {} received, {} lost ({:.1}%), {} duplicates, {} reordered",
+                format_mac(*source_mac),
+                statistics.received,
+                statistics.lost,
+                statistics.loss_rate() * 100.0,
+                statistics.duplicates,
+                statistics.reordered
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: u64 = 0x112233445566;
+
+    /// tracks frames on core 0 and spatial stream 0 by their 802.11 sequence number
+    fn track(tracker: &mut SequenceTracker, seq_nums: &[u32]) -> Vec<SequenceEvent> {
+        seq_nums
+            .iter()
+            .map(|seq_num| tracker.track(SOURCE, seq_num << 4, 0, 0))
+            .collect()
+    }
+
+    fn statistics(tracker: &SequenceTracker) -> SourceStatistics {
+        tracker.sources[&SOURCE].clone()
+    }
+
+    #[test]
+    fn strips_fragment_number() {
+        assert_eq!(sequence_number(0x1235), 0x123);
+        assert_eq!(sequence_number(0xfff0), 0xfff);
+    }
+
+    #[test]
+    fn counts_gaps_across_wraparound() {
+        let mut tracker = SequenceTracker::default();
+        let events = track(&mut tracker, &[4094, 4095, 0, 3]);
+        assert!(events[3] == SequenceEvent::InOrder { lost: 2 });
+        let statistics = statistics(&tracker);
+        assert_eq!(statistics.received, 4);
+        assert_eq!(statistics.lost, 2);
+        assert_eq!(statistics.loss_rate(), 2.0 / 6.0);
+    }
+
+    #[test]
+    fn late_frame_is_no_longer_lost() {
+        let mut tracker = SequenceTracker::default();
+        let events = track(&mut tracker, &[10, 13, 11, 11]);
+        assert!(events[2] == SequenceEvent::Reordered);
+        let statistics = statistics(&tracker);
+        assert_eq!(statistics.received, 3);
+        assert_eq!(statistics.lost, 1);
+        assert_eq!(statistics.reordered, 2);
+    }
+
+    #[test]
+    fn counts_duplicates() {
+        let mut tracker = SequenceTracker::default();
+        let events = track(&mut tracker, &[10, 10, 11]);
+        assert!(events[1] == SequenceEvent::Duplicate);
+        let statistics = statistics(&tracker);
+        assert_eq!(statistics.received, 2);
+        assert_eq!(statistics.duplicates, 1);
+        assert_eq!(statistics.lost, 0);
+    }
+
+    #[test]
+    fn resyncs_after_large_backward_step() {
+        let mut tracker = SequenceTracker::default();
+        let events = track(&mut tracker, &[1000, 100, 101]);
+        assert!(events[1] == SequenceEvent::InOrder { lost: 0 });
+        assert!(events[2] == SequenceEvent::InOrder { lost: 0 });
+        let statistics = statistics(&tracker);
+        assert_eq!(statistics.received, 3);
+        assert_eq!(statistics.lost, 0);
+    }
+
+    #[test]
+    fn chains_of_a_transmission_count_once() {
+        let mut tracker = SequenceTracker::default();
+        for seq_num in [1, 3] {
+            for core in 0..2 {
+                for spatial_stream in 0..2 {
+                    tracker.track(SOURCE, seq_num << 4, core, spatial_stream);
+                }
+            }
+        }
+        let statistics = statistics(&tracker);
+        assert_eq!(statistics.received, 2);
+        assert_eq!(statistics.lost, 1);
+        assert_eq!(statistics.duplicates, 0);
+        assert_eq!(statistics.loss_rate(), 1.0 / 3.0);
+    }
+}
@@ -0,0 +1,265 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, create_dir_all};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::commands::running::CollectionParameters;
+use crate::frame::frame_type_name;
+use crate::mac::format_mac;
+use crate::sequence::SequenceTracker;
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// user-supplied description of a capture session
+pub struct SessionMetadata {
+    pub labels: BTreeMap<String, String>,
+    pub note: Option<String>,
+}
+
+/// directory holding the captured data and a manifest describing how it was captured
+pub struct Session {
+    directory: PathBuf,
+    manifest: Manifest,
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    cspi_version: &'static str,
+    command: String,
+    labels: BTreeMap<String, String>,
+    note: Option<String>,
+    host: Host,
+    start_time: String,
+    /// not set until the session is finished, so an interrupted session can be recognized
+    end_time: Option<String>,
+    parameters: Value,
+    files: Vec<SessionFile>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    events: Vec<SessionEvent>,
+    frames: u64,
+    lost: u64,
+    loss_rate: f64,
+}
+
+#[derive(Serialize)]
+struct Host {
+    hostname: Option<String>,
+    model: Option<String>,
+    kernel: Option<String>,
+    machine: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SessionFile {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cycle: Option<u32>,
+    frames: u64,
+    lost: u64,
+    sources: Vec<SourceSummary>,
+    /// removed to stay below the disk usage cap
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    deleted: bool,
+}
+
+/// something that happened during the session, e.g. a decoder restart
+#[derive(Serialize)]
+struct SessionEvent {
+    time: String,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct SourceSummary {
+    source_mac: String,
+    received: u64,
+    lost: u64,
+    duplicates: u64,
+    reordered: u64,
+    loss_rate: f64,
+}
+
+impl Session {
+    /// creates the session directory and writes a first manifest
+    pub fn create(
+        directory: &Path,
+        command: &str,
+        parameters: Value,
+        metadata: &SessionMetadata,
+    ) -> Result<Session, String> {
+        if directory.join(MANIFEST_FILE).exists() {
+            return Err(format!(
+                "{} already contains a session, choose another output directory",
+                directory.display()
+            ));
+        }
+        create_dir_all(directory)
+            .map_err(|err| format!("Could not create {}. Error: {}", directory.display(), err))?;
+
+        let session = Session {
+            directory: directory.to_path_buf(),
+            manifest: Manifest {
+                cspi_version: env!("CARGO_PKG_VERSION"),
+                command: command.to_owned(),
+                labels: metadata.labels.clone(),
+                note: metadata.note.clone(),
+                host: Host::current(),
+                start_time: now(),
+                end_time: None,
+                parameters,
+                files: vec![],
+                events: vec![],
+                frames: 0,
+                lost: 0,
+                loss_rate: 0.0,
+            },
+        };
+        session.write_manifest()?;
+        Ok(session)
+    }
+
+    /// path of a data file in the session directory
+    pub fn path(&self, file_name: &str) -> PathBuf {
+        self.directory.join(file_name)
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// records a finished data file, with the loss statistics of its frames if they were tracked
+    pub fn add_file(
+        &mut self,
+        file_name: &str,
+        frames: u64,
+        sequence_tracker: Option<&SequenceTracker>,
+        channel: Option<String>,
+        cycle: Option<u32>,
+    ) -> Result<(), String> {
+        let sources = sequence_tracker
+            .map(|sequence_tracker| {
+                sequence_tracker
+                    .sources()
+                    .map(|(source_mac, statistics)| SourceSummary {
+                        source_mac: format_mac(*source_mac),
+                        received: statistics.received,
+                        lost: statistics.lost,
+                        duplicates: statistics.duplicates,
+                        reordered: statistics.reordered,
+                        loss_rate: statistics.loss_rate(),
+                    })
+                    .collect::<Vec<SourceSummary>>()
+            })
+            .unwrap_or_default();
+        let lost = sources.iter().map(|source| source.lost).sum();
+
+        self.manifest.files.push(SessionFile {
+            path: file_name.to_owned(),
+            channel,
+            cycle,
+            frames,
+            lost,
+            sources,
+            deleted: false,
+        });
+        self.manifest.frames += frames;
+        self.manifest.lost += lost;
+        self.manifest.loss_rate = match self.manifest.frames + self.manifest.lost {
+            0 => 0.0,
+            expected => self.manifest.lost as f64 / expected as f64,
+        };
+        self.write_manifest()
+    }
+
+    /// records that a data file was removed (its frames still count towards the session)
+    pub fn mark_deleted(&mut self, file_name: &str) -> Result<(), String> {
+        for file in self.manifest.files.iter_mut() {
+            if file.path == file_name {
+                file.deleted = true;
+            }
+        }
+        self.write_manifest()
+    }
+
+    /// records that a data file ended up under a different name, e.g. because it could not be compressed
+    pub fn rename_file(&mut self, file_name: &str, new_name: &str) -> Result<(), String> {
+        for file in self.manifest.files.iter_mut() {
+            if file.path == file_name {
+                file.path = new_name.to_owned();
+            }
+        }
+        self.write_manifest()
+    }
+
+    /// records something that happened during the session
+    pub fn add_event(&mut self, message: &str) -> Result<(), String> {
+        self.manifest.events.push(SessionEvent {
+            time: now(),
+            message: message.to_owned(),
+        });
+        self.write_manifest()
+    }
+
+    /// records the end time
+    pub fn finish(mut self) -> Result<(), String> {
+        self.manifest.end_time = Some(now());
+        self.write_manifest()
+    }
+
+    fn write_manifest(&self) -> Result<(), String> {
+        let path = self.directory.join(MANIFEST_FILE);
+        let manifest = serde_json::to_string_pretty(&self.manifest)
+            .map_err(|err| format!("Could not encode session manifest. Error: {}", err))?;
+        fs::write(&path, manifest + "\n")
+            .map_err(|err| format!("Could not write {}. Error: {}", path.display(), err))
+    }
+}
+
+impl Host {
+    fn current() -> Host {
+        let uname = uname_rs::Uname::new().ok();
+        Host {
+            hostname: uname.as_ref().map(|uname| uname.nodename.clone()),
+            model: read_trimmed("/proc/device-tree/model"),
+            kernel: uname.as_ref().map(|uname| uname.release.clone()),
+            machine: uname.as_ref().map(|uname| uname.machine.clone()),
+        }
+    }
+}
+
+/// collection parameters as stored in the manifest
+pub fn parameters_json(parameters: &CollectionParameters) -> Value {
+    json!({
+        "channel": parameters.channel.to_string(),
+        "center_frequency_mhz": parameters.channel.center_frequency(),
+        "maclist": parameters.maclist.to_string(),
+        "frame_types": parameters
+            .frame_types
+            .iter()
+            .map(|frame_type| frame_type_name(*frame_type))
+            .collect::<Vec<String>>(),
+        "cores": format!("{:#x}", parameters.cores),
+        "streams": format!("{:#x}", parameters.streams),
+    })
+}
+
+/// default session directory name, e.g. cspi-2024-05-01T12-00-00Z
+pub fn default_directory(prefix: &str) -> PathBuf {
+    let timestamp = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    PathBuf::from(format!("{}-{}", prefix, timestamp.replace(':', "-")))
+}
+
+fn now() -> String {
+    humantime::format_rfc3339_seconds(SystemTime::now()).to_string()
+}
+
+/// reads a small text file, without trailing whitespace and NUL (as in the device tree)
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|contents| contents.trim_end_matches(['\0', '\n', ' ']).to_owned())
+}
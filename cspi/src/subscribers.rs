@@ -0,0 +1,71 @@
+use crate::csi::Subscription;
+use log::{info, warn};
+use prost::Message;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// subscriptions that are not renewed within this time are dropped
+pub const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// applications that subscribed to decoded CSI by sending a Subscription to the decoder's output socket
+pub struct Subscribers {
+    subscriptions: HashMap<SocketAddr, (Instant, Subscription)>,
+}
+
+impl Subscribers {
+    pub fn new() -> Subscribers {
+        Subscribers {
+            subscriptions: HashMap::new(),
+        }
+    }
+
+    /// registers or renews all subscriptions waiting on the (non-blocking) socket and drops expired ones
+    pub fn update(&mut self, socket: &UdpSocket) {
+        let mut request_buffer = [0; 1024];
+        loop {
+            let (received_bytes, address) = match socket.recv_from(&mut request_buffer) {
+                Ok(ok) => ok,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    warn!("Could not receive subscription: {}", err);
+                    break;
+                }
+            };
+
+            let subscription = match Subscription::decode(&request_buffer[..received_bytes]) {
+                Ok(subscription) => subscription,
+                Err(err) => {
+                    warn!("Ignoring invalid subscription from {}: {}", address, err);
+                    continue;
+                }
+            };
+            if self
+                .subscriptions
+                .insert(address, (Instant::now(), subscription))
+                .is_none()
+            {
+                info!("New subscriber {}", address);
+            }
+        }
+
+        self.subscriptions.retain(|address, (renewed, _)| {
+            let active = renewed.elapsed() < SUBSCRIPTION_TIMEOUT;
+            if !active {
+                info!("Subscription of {} expired", address);
+            }
+            active
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&SocketAddr, &Subscription)> {
+        self.subscriptions
+            .iter()
+            .map(|(address, (_, subscription))| (address, subscription))
+    }
+
+    pub fn count(&self) -> usize {
+        self.subscriptions.len()
+    }
+}
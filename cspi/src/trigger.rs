@@ -0,0 +1,210 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use crate::csi::NexmonData;
+use crate::mac::{format_mac, MacFilter};
+
+/// port `cspi trigger` sends to and a triggered collection listens on
+pub const TRIGGER_PORT: u16 = 4402;
+/// number of frames per source the built-in conditions are evaluated on
+const HISTORY_LEN: usize = 50;
+/// number of frames a source needs before the built-in conditions are evaluated
+const MIN_HISTORY_LEN: usize = 10;
+
+/// event that makes a triggered collection write its buffer to a file
+#[derive(Clone, Copy)]
+pub enum Trigger {
+    /// SIGUSR1 sent to cspi
+    Signal,
+    /// datagram sent to TRIGGER_PORT, e.g. by `cspi trigger`
+    Socket,
+    /// RSSI of a frame differs from the average of its source by at least this many dB
+    RssiJump(f64),
+    /// variance of the mean CSI amplitude of a source's recent frames exceeds this
+    Variance(f64),
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trigger::Signal => write!(f, "signal"),
+            Trigger::Socket => write!(f, "socket"),
+            Trigger::RssiJump(db) => write!(f, "rssi-jump:{}", db),
+            Trigger::Variance(threshold) => write!(f, "variance:{}", threshold),
+        }
+    }
+}
+
+/// parses a trigger: signal, socket, rssi-jump:<dB> or variance:<threshold>
+pub fn parse_trigger(trigger: &str) -> Result<Trigger, String> {
+    let (name, value) = match trigger.split_once(':') {
+        Some((name, value)) => (name, Some(value)),
+        None => (trigger, None),
+    };
+    let threshold = || -> Result<f64, String> {
+        value
+            .ok_or(format!(
+                "Trigger '{}' needs a threshold, e.g. {}:10",
+                name, name
+            ))?
+            .parse()
+            .ok()
+            .filter(|threshold: &f64| *threshold > 0.0)
+            .ok_or(format!(
+                "Invalid threshold in trigger '{}' (expected a positive number)",
+                trigger
+            ))
+    };
+
+    match (name.to_lowercase().as_str(), value) {
+        ("signal", None) => Ok(Trigger::Signal),
+        ("socket", None) => Ok(Trigger::Socket),
+        ("rssi-jump", _) => Ok(Trigger::RssiJump(threshold()?)),
+        ("variance", _) => Ok(Trigger::Variance(threshold()?)),
+        ("signal" | "socket", Some(_)) => {
+            Err(format!("Trigger '{}' does not take a threshold", name))
+        }
+        _ => Err(format!(
+            "Unknown trigger '{}' (expected signal, socket, rssi-jump:<dB> or variance:<threshold>)",
+            trigger
+        )),
+    }
+}
+
+/// settings of a triggered collection
+pub struct TriggerOptions {
+    pub triggers: Vec<Trigger>,
+    /// only these sources can fire the built-in conditions
+    pub sources: MacFilter,
+    /// CSI received before a trigger that is kept
+    pub pre_trigger: Duration,
+    /// CSI received after a trigger that is kept
+    pub post_trigger: Duration,
+}
+
+/// recent frames of a source
+#[derive(Default)]
+struct SourceHistory {
+    rssi: VecDeque<i32>,
+    amplitude: VecDeque<f64>,
+}
+
+impl SourceHistory {
+    fn push(&mut self, rssi: i32, amplitude: f64) {
+        if self.rssi.len() == HISTORY_LEN {
+            self.rssi.pop_front();
+            self.amplitude.pop_front();
+        }
+        self.rssi.push_back(rssi);
+        self.amplitude.push_back(amplitude);
+    }
+}
+
+/// evaluates the built-in trigger conditions (RSSI jump, amplitude variance) on every source
+pub struct ConditionMonitor {
+    rssi_jump: Option<f64>,
+    variance: Option<f64>,
+    sources: MacFilter,
+    history: HashMap<u64, SourceHistory>,
+}
+
+impl ConditionMonitor {
+    pub fn new(options: &TriggerOptions) -> ConditionMonitor {
+        let mut monitor = ConditionMonitor {
+            rssi_jump: None,
+            variance: None,
+            sources: options.sources.clone(),
+            history: HashMap::new(),
+        };
+        for trigger in &options.triggers {
+            match *trigger {
+                Trigger::RssiJump(db) => monitor.rssi_jump = Some(db),
+                Trigger::Variance(threshold) => monitor.variance = Some(threshold),
+                Trigger::Signal | Trigger::Socket => {}
+            }
+        }
+        monitor
+    }
+
+    /// adds a frame to the history of its source, returns why a trigger fired if one did
+    pub fn check(&mut self, nexmon_data: &NexmonData) -> Option<String> {
+        if (self.rssi_jump.is_none() && self.variance.is_none())
+            || !self.sources.matches(nexmon_data.source_mac)
+        {
+            return None;
+        }
+
+        let history = self.history.entry(nexmon_data.source_mac).or_default();
+        let amplitude = mean_amplitude(nexmon_data);
+        let mut reason = None;
+
+        if history.rssi.len() >= MIN_HISTORY_LEN {
+            let average_rssi = history.rssi.iter().sum::<i32>() as f64 / history.rssi.len() as f64;
+            let jump = nexmon_data.rssi as f64 - average_rssi;
+            if self.rssi_jump.is_some_and(|db| jump.abs() >= db) {
+                reason = Some(format!(
+                    "RSSI of {} jumped by {:.1} dB to {} dBm",
+                    format_mac(nexmon_data.source_mac),
+                    jump,
+                    nexmon_data.rssi
+                ));
+            }
+        }
+        history.push(nexmon_data.rssi, amplitude);
+
+        if reason.is_none() && history.amplitude.len() >= MIN_HISTORY_LEN {
+            let variance = variance(&history.amplitude);
+            if self.variance.is_some_and(|threshold| variance >= threshold) {
+                reason = Some(format!(
+                    "Amplitude variance of {} reached {:.1}",
+                    format_mac(nexmon_data.source_mac),
+                    variance
+                ));
+            }
+        }
+
+        // start over, so the same change does not fire again right away
+        if reason.is_some() {
+            self.history.remove(&nexmon_data.source_mac);
+        }
+        reason
+    }
+}
+
+/// mean amplitude over all subcarriers of a frame
+fn mean_amplitude(nexmon_data: &NexmonData) -> f64 {
+    if nexmon_data.csi.is_empty() {
+        return 0.0;
+    }
+    nexmon_data
+        .csi
+        .iter()
+        .map(|csi| (csi.real as f64).hypot(csi.imaginary as f64))
+        .sum::<f64>()
+        / nexmon_data.csi.len() as f64
+}
+
+fn variance(values: &VecDeque<f64>) -> f64 {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / values.len() as f64
+}
+
+/// fires the socket trigger of a running triggered collection
+pub fn fire_trigger(reason: &Option<String>) -> Result<(), String> {
+    let socket = UdpSocket::bind("127.0.0.1:0")
+        .map_err(|err| format!("Could not open socket. Error: {}", err))?;
+    socket
+        .send_to(
+            reason.as_deref().unwrap_or("").as_bytes(),
+            ("127.0.0.1", TRIGGER_PORT),
+        )
+        .map_err(|err| format!("Could not send trigger. Error: {}", err))?;
+    println!("Trigger sent to port {}", TRIGGER_PORT);
+    Ok(())
+}
@@ -1,5 +1,6 @@
 import argparse
-import os
+import json
+import lzma
 import proto.csi_pb2 as csi_pb2
 import matplotlib.pyplot as plt
 import numpy as np
@@ -9,14 +10,38 @@ if __name__ == "__main__":
                     prog='colormap_visualizer',
                     description='Creates spectrogram from passed CSI pcap file')
     
-    parser.add_argument('csi_path', help="Path to the file of protobuf encoded CSI packets")
+    parser.add_argument('csi_path', help="Path to the file of protobuf encoded CSI packets, or a spectrogram saved by `cspi spectrogram --save <file>.npy`")
+    parser.add_argument('--group', type=int, help="Subcarrier group of the spectrogram to show (default: the average of all groups)")
     args = parser.parse_args()
 
+    # Doppler spectrogram computed by cspi, its axes are in the .json file next to it
+    if args.csi_path.endswith('.npy'):
+        power = np.load(args.csi_path)
+        with open(args.csi_path[:-len('.npy')] + '.json') as axes_file:
+            axes = json.load(axes_file)
+        power = power.mean(axis=1) if args.group is None else power[:, args.group, :]
+
+        fig, ax = plt.subplots()
+        pcm = ax.pcolormesh(axes['times'], axes['frequencies'], power.T, shading='nearest')
+        fig.colorbar(pcm, label='Power (dB)')
+        plt.xlabel('Time since ' + axes['start'] + ' (s)')
+        plt.ylabel('Doppler frequency (Hz)')
+        plt.savefig('output.png')
+        raise SystemExit
+
     with open(args.csi_path, 'rb') as pcap_file:
         pcap_data = pcap_file.read()
-    pcap_file_length = os.path.getsize(args.csi_path)
+
+    # captures written with --compress are decompressed transparently
+    if pcap_data.startswith(b'\xfd7zXZ\x00'):
+        pcap_data = lzma.decompress(pcap_data)
+    elif pcap_data.startswith(b'\x28\xb5\x2f\xfd'):
+        import zstandard
+        pcap_data = zstandard.ZstdDecompressor().decompressobj().decompress(pcap_data)
+    pcap_file_length = len(pcap_data)
     
     csi_list = []
+    timestamps = []
     position = 0
     while position < pcap_file_length:
         payload_length = int.from_bytes(pcap_data[position : (position + 4)], byteorder='little', signed=False)
@@ -33,6 +58,7 @@ if __name__ == "__main__":
 
         position = position + payload_length
         csi_list.append(nexmon_data.csi)
+        timestamps.append(nexmon_data.timestamp)
 
     data = []
 
@@ -44,7 +70,13 @@ if __name__ == "__main__":
         data.append(element_list)
 
     fig, ax = plt.subplots()
-    pcm = ax.pcolormesh(np.abs(data))
+    # packets are placed at their receive time, captures from before the decoder recorded it only have their order
+    if all(timestamps):
+        times = (np.array(timestamps) - timestamps[0]) / 1e6
+        pcm = ax.pcolormesh(np.arange(len(data[0])), times, np.abs(data), shading='nearest')
+        plt.ylabel('Time (s)')
+    else:
+        pcm = ax.pcolormesh(np.abs(data))
+        plt.ylabel('Packet number')
     plt.xlabel('Subcarrier')
-    plt.ylabel('Packet number')
     plt.savefig('output.png')
@@ -13,15 +13,23 @@ _sym_db = _symbol_database.Default()
 
 
 
-DESCRIPTOR = _descriptor_pool.Default().AddSerializedFile(b'\n\tcsi.proto\x12\x03\x63si\"d\n\nNexmonData\x12\x15\n\x03\x63si\x18\x01 \x03(\x0b\x32\x08.csi.CSI\x12\x0c\n\x04rssi\x18\x02 \x01(\x05\x12\x0c\n\x04\x66\x63tl\x18\x03 \x01(\r\x12\x12\n\nsource_mac\x18\x04 \x01(\x04\x12\x0f\n\x07seq_num\x18\x05 \x01(\r\"&\n\x03\x43SI\x12\x0c\n\x04real\x18\x01 \x01(\x05\x12\x11\n\timaginary\x18\x02 \x01(\x05\x62\x06proto3')
+DESCRIPTOR = _descriptor_pool.Default().AddSerializedFile(b'\n\tcsi.proto\x12\x03\x63si\"\xb5\x03\n\nNexmonData\x12\x15\n\x03\x63si\x18\x01 \x03(\x0b\x32\x08.csi.CSI\x12\x0c\n\x04rssi\x18\x02 \x01(\x05\x12\x0c\n\x04\x66\x63tl\x18\x03 \x01(\r\x12\x12\n\nsource_mac\x18\x04 \x01(\x04\x12\x0f\n\x07seq_num\x18\x05 \x01(\r\x12\x0c\n\x04\x63ore\x18\x06 \x01(\r\x12\x16\n\x0espatial_stream\x18\x07 \x01(\r\x12\x0b\n\x03gap\x18\x08 \x01(\r\x12\"\n\nframe_type\x18\t \x01(\x0e\x32\x0e.csi.FrameType\x12\x0f\n\x07\x63hannel\x18\n \x01(\r\x12\x11\n\tbandwidth\x18\x0b \x01(\r\x12\x11\n\tamplitude\x18\x0c \x03(\x02\x12\r\n\x05phase\x18\r \x03(\x02\x12\x12\n\nsubcarrier\x18\x0e \x03(\x11\x12,\n\x0fsubcarrier_type\x18\x0f \x03(\x0e\x32\x13.csi.SubcarrierType\x12\x17\n\x0fsanitized_phase\x18\x10 \x03(\x02\x12\x12\n\nnormalized\x18\x11 \x01(\x08\x12\x11\n\ttimestamp\x18\x12 \x01(\x04\x12\x1a\n\x12\x66iltered_amplitude\x18\x13 \x03(\x02\x12\x14\n\x0cinterpolated\x18\x14 \x01(\x08\"t\n\x08MimoData\x12\x12\n\nsource_mac\x18\x01 \x01(\x04\x12\x0f\n\x07seq_num\x18\x02 \x01(\r\x12\"\n\nframe_type\x18\x03 \x01(\x0e\x32\x0e.csi.FrameType\x12\x1f\n\x06\x63hains\x18\x04 \x03(\x0b\x32\x0f.csi.NexmonData\"&\n\x03\x43SI\x12\x0c\n\x04real\x18\x01 \x01(\x05\x12\x11\n\timaginary\x18\x02 \x01(\x05\"\x9d\x01\n\x0cSubscription\x12\x0c\n\x04gaps\x18\x01 \x01(\x08\x12#\n\x0b\x66rame_types\x18\x02 \x03(\x0e\x32\x0e.csi.FrameType\x12\x0c\n\x04mimo\x18\x03 \x01(\x08\x12\x0f\n\x07\x64\x65rived\x18\x04 \x01(\x08\x12\x16\n\x0esanitize_phase\x18\x05 \x01(\x08\x12\x11\n\tnormalize\x18\x06 \x01(\x08\x12\x10\n\x08\x66iltered\x18\x07 \x01(\x08*x\n\x0eSubcarrierType\x12\x18\n\x14SUBCARRIER_TYPE_DATA\x10\x00\x12\x19\n\x15SUBCARRIER_TYPE_PILOT\x10\x01\x12\x19\n\x15SUBCARRIER_TYPE_GUARD\x10\x02\x12\x16\n\x12SUBCARRIER_TYPE_DC\x10\x03*\x93\x05\n\tFrameType\x12\x14\n\x10\x46RAME_TYPE_OTHER\x10\x00\x12\"\n\x1e\x46RAME_TYPE_ASSOCIATION_REQUEST\x10\x01\x12#\n\x1f\x46RAME_TYPE_ASSOCIATION_RESPONSE\x10\x02\x12$\n FRAME_TYPE_REASSOCIATION_REQUEST\x10\x03\x12%\n!FRAME_TYPE_REASSOCIATION_RESPONSE\x10\x04\x12\x1c\n\x18\x46RAME_TYPE_PROBE_REQUEST\x10\x05\x12\x1d\n\x19\x46RAME_TYPE_PROBE_RESPONSE\x10\x06\x12\x15\n\x11\x46RAME_TYPE_BEACON\x10\x07\x12\x1d\n\x19\x46RAME_TYPE_DISASSOCIATION\x10\x08\x12\x1d\n\x19\x46RAME_TYPE_AUTHENTICATION\x10\t\x12\x1f\n\x1b\x46RAME_TYPE_DEAUTHENTICATION\x10\n\x12\x15\n\x11\x46RAME_TYPE_ACTION\x10\x0b\x12\x1f\n\x1b\x46RAME_TYPE_NDP_ANNOUNCEMENT\x10\x0c\x12 \n\x1c\x46RAME_TYPE_BLOCK_ACK_REQUEST\x10\r\x12\x18\n\x14\x46RAME_TYPE_BLOCK_ACK\x10\x0e\x12\x16\n\x12\x46RAME_TYPE_PS_POLL\x10\x0f\x12\x12\n\x0e\x46RAME_TYPE_RTS\x10\x10\x12\x12\n\x0e\x46RAME_TYPE_CTS\x10\x11\x12\x12\n\x0e\x46RAME_TYPE_ACK\x10\x12\x12\x13\n\x0f\x46RAME_TYPE_DATA\x10\x13\x12\x18\n\x14\x46RAME_TYPE_NULL_DATA\x10\x14\x12\x17\n\x13\x46RAME_TYPE_QOS_DATA\x10\x15\x12\x17\n\x13\x46RAME_TYPE_QOS_NULL\x10\x16\x62\x06proto3')
 
 _builder.BuildMessageAndEnumDescriptors(DESCRIPTOR, globals())
 _builder.BuildTopDescriptorsAndMessages(DESCRIPTOR, 'csi_pb2', globals())
 if _descriptor._USE_C_DESCRIPTORS == False:
 
   DESCRIPTOR._options = None
-  _NEXMONDATA._serialized_start=18
-  _NEXMONDATA._serialized_end=118
-  _CSI._serialized_start=120
-  _CSI._serialized_end=158
+  _NEXMONDATA._serialized_start=19
+  _NEXMONDATA._serialized_end=456
+  _MIMODATA._serialized_start=458
+  _MIMODATA._serialized_end=574
+  _CSI._serialized_start=576
+  _CSI._serialized_end=614
+  _SUBSCRIPTION._serialized_start=617
+  _SUBSCRIPTION._serialized_end=774
+  _SUBCARRIERTYPE._serialized_start=776
+  _SUBCARRIERTYPE._serialized_end=896
+  _FRAMETYPE._serialized_start=899
+  _FRAMETYPE._serialized_end=1558
 # @@protoc_insertion_point(module_scope)